@@ -0,0 +1,154 @@
+use log::warn;
+use serde::Deserialize;
+use std::{cell::Cell, collections::HashSet, fs, path::{Path, PathBuf}, process::Command, time::{Duration, SystemTime}};
+
+// Seconds a file has to stay unchanged before it is touched, when the rule does not set quietperiod
+pub const DEFAULT_QUIET_PERIOD: u64 = 300;
+
+// How to find out if another process still has a file open
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenFileCheck {
+    // Look through /proc on Linux, other systems only get the quiet period
+    #[default]
+    Auto,
+    // Ask lsof about each file, for systems without /proc
+    Lsof,
+    Off,
+}
+
+// What decides that a file is still being written and has to wait for a later run
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ActiveFileCheck {
+    pub quietperiod: Option<u64>,
+    pub openfilecheck: OpenFileCheck,
+}
+
+impl ActiveFileCheck {
+    pub fn quiet_period(&self) -> u64 {
+        self.quietperiod.unwrap_or(DEFAULT_QUIET_PERIOD)
+    }
+
+    // Collect the open files once up front so checking each file is cheap
+    pub fn start(&self) -> ActiveFiles {
+        let open = match self.openfilecheck {
+            OpenFileCheck::Auto => open_files(),
+            _ => None,
+        };
+        ActiveFiles { check: *self, open, lsof_failed: Cell::new(false) }
+    }
+}
+
+pub struct ActiveFiles {
+    check: ActiveFileCheck,
+    open: Option<HashSet<PathBuf>>,
+    lsof_failed: Cell<bool>,
+}
+
+impl ActiveFiles {
+    // Why the file looks like it is still being written, None when it is safe to compress or move
+    pub fn reason(&self, path: &Path, metadata: &fs::Metadata) -> Option<String> {
+        if let Ok(modified) = metadata.modified() {
+            let unchanged = SystemTime::now().duration_since(modified).unwrap_or_default();
+            if unchanged < Duration::from_secs(self.check.quiet_period()) {
+                return Some(format!("it changed {} second(s) ago", unchanged.as_secs()));
+            }
+        }
+
+        if let Some(open) = &self.open {
+            if fs::canonicalize(path).is_ok_and(|path| open.contains(&path)) {
+                return Some("another process has it open".to_string());
+            }
+        }
+
+        if self.check.openfilecheck == OpenFileCheck::Lsof && !self.lsof_failed.get() {
+            match Command::new("lsof").arg("-t").arg("--").arg(path).output() {
+                Ok(output) if !output.stdout.is_empty() => return Some("lsof reports it is open".to_string()),
+                Ok(_) => (),
+                Err(e) => {
+                    warn!("Could not run lsof to check for open files, only the quiet period is checked: {}", e);
+                    self.lsof_failed.set(true);
+                },
+            }
+        }
+
+        None
+    }
+}
+
+// Every file any process has open, from the /proc/<pid>/fd links. Processes of other users
+// can only be seen when running as root
+#[cfg(target_os = "linux")]
+fn open_files() -> Option<HashSet<PathBuf>> {
+    let mut open = HashSet::new();
+    for process in fs::read_dir("/proc").ok()?.filter_map(|entry| entry.ok()) {
+        if !process.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(descriptors) = fs::read_dir(process.path().join("fd")) else { continue };
+        for descriptor in descriptors.filter_map(|entry| entry.ok()) {
+            if let Ok(target) = fs::read_link(descriptor.path()) {
+                open.insert(target);
+            }
+        }
+    }
+    Some(open)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_files() -> Option<HashSet<PathBuf>> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+    use std::fs::File;
+
+    fn test_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("logrc-active-test-{}-{}.log", name, std::process::id()));
+        fs::write(&path, "line\n").unwrap();
+        path
+    }
+
+    fn age(path: &Path, seconds: u64) {
+        filetime::set_file_mtime(path, FileTime::from_system_time(SystemTime::now() - Duration::from_secs(seconds))).unwrap();
+    }
+
+    #[test]
+    fn recently_changed_files_wait_for_the_quiet_period() {
+        let path = test_file("quiet");
+        let check = ActiveFileCheck { quietperiod: Some(60), openfilecheck: OpenFileCheck::Off };
+
+        age(&path, 10);
+        let reason = check.start().reason(&path, &fs::metadata(&path).unwrap());
+        assert!(reason.is_some_and(|reason| reason.starts_with("it changed")));
+
+        age(&path, 120);
+        assert_eq!(check.start().reason(&path, &fs::metadata(&path).unwrap()), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn quiet_period_defaults_when_not_set() {
+        assert_eq!(ActiveFileCheck::default().quiet_period(), DEFAULT_QUIET_PERIOD);
+        assert_eq!(ActiveFileCheck { quietperiod: Some(0), ..ActiveFileCheck::default() }.quiet_period(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn files_another_process_has_open_wait() {
+        let path = test_file("open");
+        age(&path, 120);
+        let check = ActiveFileCheck { quietperiod: Some(60), openfilecheck: OpenFileCheck::Auto };
+
+        // The check does not leave out this process, so a file the test holds open is reported
+        let open = File::open(&path).unwrap();
+        assert_eq!(check.start().reason(&path, &fs::metadata(&path).unwrap()).as_deref(), Some("another process has it open"));
+
+        drop(open);
+        assert_eq!(check.start().reason(&path, &fs::metadata(&path).unwrap()), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
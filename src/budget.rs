@@ -0,0 +1,144 @@
+use std::{path::{Path, PathBuf}, sync::Mutex, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+
+// An archive that was left part way through a date group when the run ran out of budget.
+// The next run adds the rest of the group to it rather than starting a new archive
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PendingArchive {
+    pub directory: PathBuf,
+    pub filenamecontains: String,
+    pub date: String,
+    pub path: PathBuf,
+}
+
+impl PendingArchive {
+    fn is_for(&self, directory: &Path, filenamecontains: &str, date: &str) -> bool {
+        self.directory == directory && self.filenamecontains == filenamecontains && self.date == date
+    }
+}
+
+// How much work a run may still do. Shared by every rule in the run, including across worker threads
+#[derive(Default)]
+pub struct RunBudget {
+    deadline: Option<Instant>,
+    files_left: Mutex<Option<u64>>,
+    pending: Mutex<Vec<PendingArchive>>,
+}
+
+impl RunBudget {
+    pub fn new(max_runtime: Option<Duration>, max_files: Option<u64>, pending: Vec<PendingArchive>) -> RunBudget {
+        RunBudget {
+            deadline: max_runtime.map(|max_runtime| Instant::now() + max_runtime),
+            files_left: Mutex::new(max_files),
+            pending: Mutex::new(pending),
+        }
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || *self.files_left.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) == Some(0)
+    }
+
+    // Claim one file from the budget, false when there is no budget left
+    pub fn take_file(&self) -> bool {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+
+        let mut files_left = self.files_left.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match files_left.as_mut() {
+            Some(0) => false,
+            Some(left) => {
+                *left -= 1;
+                true
+            },
+            None => true,
+        }
+    }
+
+    // The archive to carry on with for a date group, if the last run left one and it is still there
+    pub fn pending_archive(&self, directory: &Path, filenamecontains: &str, date: &str) -> Option<PathBuf> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+            .find(|pending| pending.is_for(directory, filenamecontains, date))
+            .map(|pending| pending.path.clone())
+            .filter(|path| path.is_file())
+    }
+
+    pub fn set_pending_archive(&self, archive: PendingArchive) {
+        let mut pending = self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.retain(|other| !other.is_for(&archive.directory, &archive.filenamecontains, &archive.date));
+        pending.push(archive);
+    }
+
+    pub fn clear_pending_archive(&self, directory: &Path, filenamecontains: &str, date: &str) {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|pending| !pending.is_for(directory, filenamecontains, date));
+    }
+
+    pub fn pending_archives(&self) -> Vec<PendingArchive> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn pending(directory: &Path, date: &str, path: PathBuf) -> PendingArchive {
+        PendingArchive { directory: directory.to_path_buf(), filenamecontains: "app".to_string(), date: date.to_string(), path }
+    }
+
+    #[test]
+    fn take_file_stops_at_the_file_limit() {
+        let budget = RunBudget::new(None, Some(2), Vec::new());
+        assert!(!budget.exhausted());
+        assert!(budget.take_file());
+        assert!(budget.take_file());
+        assert!(!budget.take_file());
+        assert!(budget.exhausted());
+    }
+
+    #[test]
+    fn take_file_without_limits_always_succeeds() {
+        let budget = RunBudget::default();
+        assert!((0..1000).all(|_| budget.take_file()));
+        assert!(!budget.exhausted());
+    }
+
+    #[test]
+    fn take_file_stops_at_the_deadline() {
+        let budget = RunBudget::new(Some(Duration::ZERO), None, Vec::new());
+        assert!(!budget.take_file());
+        assert!(budget.exhausted());
+    }
+
+    #[test]
+    fn pending_archives_are_found_only_while_the_archive_is_there() {
+        let dir = std::env::temp_dir().join(format!("logrc-budget-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("2026-01-01_app-1.zip");
+        fs::write(&archive, b"").unwrap();
+
+        let budget = RunBudget::new(None, None, vec![pending(&dir, "2026-01-01", archive.clone()), pending(&dir, "2026-01-02", dir.join("gone.zip"))]);
+        assert_eq!(budget.pending_archive(&dir, "app", "2026-01-01"), Some(archive.clone()));
+        assert_eq!(budget.pending_archive(&dir, "web", "2026-01-01"), None);
+        assert_eq!(budget.pending_archive(&dir, "app", "2026-01-02"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(budget.pending_archive(&dir, "app", "2026-01-01"), None);
+    }
+
+    #[test]
+    fn pending_archives_are_replaced_and_cleared_per_date_group() {
+        let dir = Path::new("/logs");
+        let budget = RunBudget::new(None, None, vec![pending(dir, "2026-01-01", dir.join("a-1.zip"))]);
+
+        budget.set_pending_archive(pending(dir, "2026-01-01", dir.join("a-2.zip")));
+        budget.set_pending_archive(pending(dir, "2026-01-02", dir.join("b-1.zip")));
+        assert_eq!(budget.pending_archives(), vec![pending(dir, "2026-01-01", dir.join("a-2.zip")), pending(dir, "2026-01-02", dir.join("b-1.zip"))]);
+
+        budget.clear_pending_archive(dir, "app", "2026-01-01");
+        assert_eq!(budget.pending_archives(), vec![pending(dir, "2026-01-02", dir.join("b-1.zip"))]);
+    }
+}
@@ -0,0 +1,118 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
+
+use crate::lock::LockMode;
+
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  2  Config error
+  3  Partial failure, some directory rules failed
+  4  Total failure, every directory rule failed
+  5  Another run holds the lock";
+
+#[derive(Parser)]
+#[command(name = "LogRC", about = "Log Retention and Compression.", after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Path to the config file
+    #[arg(long, global = true, env = "LOGRC_CONFIG", default_value = "LogRC.toml")]
+    pub config: PathBuf,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run retention, compression and moves for every directory rule (default)
+    Run(RunArgs),
+    /// Keep running and run each directory rule on its schedule until stopped. Reloads the config when it changes or on SIGHUP
+    Daemon(DaemonArgs),
+    /// Check the config file and report any problems
+    Validate,
+    /// Show every action a run would take without changing anything on disk
+    Plan(PlanArgs),
+    /// List the directory rules in the config file
+    ListRules,
+    /// Rewrite the config file and its included files in the newest schema, keeping a .bak copy of each
+    MigrateConfig,
+    /// Print the version
+    Version,
+}
+
+#[derive(Args, Default)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub selection: RuleSelection,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Print the plan instead of running it
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    #[command(flatten)]
+    pub selection: RuleSelection,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Also watch each compress rule's path and compress files once they stop changing
+    #[arg(long)]
+    pub watch: bool,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub selection: RuleSelection,
+
+    /// Output format for the plan
+    #[arg(long, value_enum, default_value_t = PlanFormat::Text)]
+    pub format: PlanFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum PlanFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args, Default)]
+pub struct LockArgs {
+    /// What to do when another run already holds the lock
+    #[arg(long = "lock", value_enum, default_value_t = LockMode::Fail)]
+    pub mode: LockMode,
+
+    /// Give up waiting for the lock after this many seconds
+    #[arg(long = "lock-timeout", value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+}
+
+impl LockArgs {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+}
+
+#[derive(Args, Default)]
+pub struct RuleSelection {
+    /// Only run the directory rules matching this number, name, path or filenamecontains. Can be used more than once
+    #[arg(long = "only", value_name = "RULE")]
+    pub only: Vec<String>,
+}
+
+impl RuleSelection {
+    // A rule is selected by its 1 based position, its name, its path or its filenamecontains
+    pub fn selects(&self, index: usize, name: Option<&str>, path: &str, filenamecontains: &str) -> bool {
+        self.only.is_empty() || self.only.iter().any(|rule| {
+            rule.parse::<usize>().map(|number| number == index + 1).unwrap_or(false)
+                || name == Some(rule.as_str())
+                || rule == path
+                || rule == filenamecontains
+        })
+    }
+}
@@ -0,0 +1,934 @@
+use crate::{migrate, placeholders, priority, schedule};
+use crate::{ActiveFileCheck, IoLimits, OpenFileCheck, RetryPolicy, TransientError, DEFAULT_RETRY_ATTEMPTS, DEFAULT_RETRY_BACKOFF, DEFAULT_RETRY_ON, SftpDestination, StatusFormat, rule_label};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, fs, path::{Path, PathBuf}, time::Duration};
+use toml::{Table, Value};
+
+// Keys each table accepts. These have to be kept in step with the structs below
+const TOP_LEVEL_KEYS: &[&str] = &["version", "application", "defaults", "profiles", "directory"];
+const APPLICATION_KEYS: &[&str] = &["logretentionindays", "summaryfile", "metricsfile", "include", "schedule", "lockfile", "workers", "nice", "ionice", "maxruntime", "maxfilesperrun", "statefile"];
+const INCLUDE_TOP_LEVEL_KEYS: &[&str] = &["version", "directory"];
+const DIRECTORY_KEYS: &[&str] = &["name", "path", "profile", "filenamecontains", "retentionindays", "compress", "movetopath", "sftp", "statusfile", "statusformat", "schedule", "quietperiod", "openfilecheck", "maxreadrate", "maxwriterate", "retryattempts", "retrybackoff", "retryon"];
+const DEFAULTS_KEYS: &[&str] = &["filenamecontains", "retentionindays", "compress", "movetopath", "sftp", "statusfile", "statusformat", "schedule", "quietperiod", "openfilecheck", "maxreadrate", "maxwriterate", "retryattempts", "retrybackoff", "retryon"];
+const SFTP_KEYS: &[&str] = &["host", "port", "username", "privatekey", "publickey", "passphrase", "knownhosts", "skiphostkeycheck", "remotepath"];
+
+// Settings as written in [defaults], a [profiles] entry or a directory rule. Anything left out is filled in from the next level down
+#[derive(Deserialize, Clone, Default)]
+struct DirectorySettings {
+    name: Option<String>,
+    path: Option<String>,
+    profile: Option<String>,
+    filenamecontains: Option<String>,
+    retentionindays: Option<u64>,
+    compress: Option<bool>,
+    movetopath: Option<String>,
+    sftp: Option<SftpDestination>,
+    statusfile: Option<String>,
+    statusformat: Option<StatusFormat>,
+    schedule: Option<String>,
+    quietperiod: Option<u64>,
+    openfilecheck: Option<OpenFileCheck>,
+    maxreadrate: Option<u64>,
+    maxwriterate: Option<u64>,
+    retryattempts: Option<u32>,
+    retrybackoff: Option<u64>,
+    retryon: Option<Vec<TransientError>>,
+}
+
+impl DirectorySettings {
+    fn or(self, fallback: &DirectorySettings) -> DirectorySettings {
+        DirectorySettings {
+            // A name belongs to one rule, so it is never filled in from a profile or [defaults]
+            name: self.name,
+            path: self.path.or_else(|| fallback.path.clone()),
+            profile: self.profile.or_else(|| fallback.profile.clone()),
+            filenamecontains: self.filenamecontains.or_else(|| fallback.filenamecontains.clone()),
+            retentionindays: self.retentionindays.or(fallback.retentionindays),
+            compress: self.compress.or(fallback.compress),
+            movetopath: self.movetopath.or_else(|| fallback.movetopath.clone()),
+            sftp: self.sftp.or_else(|| fallback.sftp.clone()),
+            statusfile: self.statusfile.or_else(|| fallback.statusfile.clone()),
+            statusformat: self.statusformat.or(fallback.statusformat),
+            schedule: self.schedule.or_else(|| fallback.schedule.clone()),
+            quietperiod: self.quietperiod.or(fallback.quietperiod),
+            openfilecheck: self.openfilecheck.or(fallback.openfilecheck),
+            maxreadrate: self.maxreadrate.or(fallback.maxreadrate),
+            maxwriterate: self.maxwriterate.or(fallback.maxwriterate),
+            retryattempts: self.retryattempts.or(fallback.retryattempts),
+            retrybackoff: self.retrybackoff.or(fallback.retrybackoff),
+            retryon: self.retryon.or_else(|| fallback.retryon.clone()),
+        }
+    }
+
+    // path, filenamecontains and retentionindays have to come from somewhere, the rest can be left out
+    fn resolve(self, rule: usize, errors: &mut Vec<ConfigError>) -> Option<Directory> {
+        let mut missing = |field: &str| errors.push(ConfigError::new(Some(rule), field, ConfigErrorKind::Missing, "should be set on the rule, its profile or in [defaults]".to_string()));
+
+        let path = self.path.or_else(|| { missing("path"); None });
+        let filenamecontains = self.filenamecontains.or_else(|| { missing("filenamecontains"); None });
+        let retentionindays = self.retentionindays.or_else(|| { missing("retentionindays"); None });
+
+        Some(Directory {
+            name: self.name,
+            path: path?,
+            filenamecontains: filenamecontains?,
+            retentionindays: retentionindays?,
+            compress: self.compress.unwrap_or(false),
+            movetopath: self.movetopath.unwrap_or_default(),
+            sftp: self.sftp,
+            statusfile: self.statusfile,
+            statusformat: self.statusformat,
+            schedule: self.schedule,
+            active: ActiveFileCheck { quietperiod: self.quietperiod, openfilecheck: self.openfilecheck.unwrap_or_default() },
+            limits: IoLimits { maxreadrate: self.maxreadrate, maxwriterate: self.maxwriterate },
+            retry: RetryPolicy {
+                attempts: self.retryattempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS),
+                backoff: Duration::from_millis(self.retrybackoff.unwrap_or(DEFAULT_RETRY_BACKOFF)),
+                retryon: self.retryon.unwrap_or_else(|| DEFAULT_RETRY_ON.to_vec()),
+            },
+            source: PathBuf::new(),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Directory {
+    // Optional name that stays the same when rules are added, removed or reordered
+    pub name: Option<String>,
+    pub path: String,
+    pub filenamecontains: String,
+    pub retentionindays: u64,
+    pub compress: bool,
+    pub movetopath: String,
+    pub sftp: Option<SftpDestination>,
+    pub statusfile: Option<String>,
+    pub statusformat: Option<StatusFormat>,
+    // Cron schedule for daemon mode, falls back on [application]schedule
+    pub schedule: Option<String>,
+    // Seconds a file has to stay unchanged and how to check that nothing has it open before it is compressed or moved
+    pub active: ActiveFileCheck,
+    // maxreadrate and maxwriterate in bytes per second
+    pub limits: IoLimits,
+    // How often to try opening, moving and removing files that are locked or briefly out of reach
+    pub retry: RetryPolicy,
+    // The config file the rule was read from
+    pub source: PathBuf,
+}
+
+pub struct Directories {
+    pub directory: Vec<Directory>,
+}
+
+#[derive(Deserialize, PartialEq)]
+pub struct Application {
+    pub logretentionindays: u64,
+    pub summaryfile: Option<String>,
+    pub metricsfile: Option<String>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    pub schedule: Option<String>,
+    pub lockfile: Option<String>,
+    pub workers: Option<usize>,
+    // CPU and I/O priority for the whole process
+    pub nice: Option<i32>,
+    pub ionice: Option<String>,
+    // Stop a run after maxruntime minutes or maxfilesperrun files, the state file says where the next run carries on
+    pub maxruntime: Option<u64>,
+    pub maxfilesperrun: Option<u64>,
+    pub statefile: Option<String>,
+}
+
+impl Application {
+    // Rules run one at a time unless workers is set
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or(1)
+    }
+
+    // How long a run may take, unlimited unless maxruntime is set
+    pub fn max_runtime(&self) -> Option<Duration> {
+        self.maxruntime.map(|minutes| Duration::from_secs(minutes * 60))
+    }
+}
+
+pub struct ConfigFile {
+    pub directories: Directories,
+    pub application: Application,
+    // The schema version the main file was written for, before it was migrated
+    pub version: i64,
+    // The main config file followed by every included file
+    pub files: Vec<PathBuf>,
+    // The include patterns relative to the main config file's directory, to spot include files that are added later
+    pub include_patterns: Vec<String>,
+    // Problems found while loading that do not stop the config from being used, such as unknown keys
+    load_errors: Vec<ConfigError>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigErrorKind {
+    Read,
+    Parse,
+    InvalidValue,
+    Missing,
+    UnknownProfile,
+    UnknownKey,
+    OutOfRange,
+    Blank,
+    InvalidCharacter,
+    NotADirectory,
+    ConflictingSettings,
+    DestinationInsidePath,
+    OverlappingRules,
+    NoMatchingRules,
+    DuplicateRule,
+    UndefinedVariable,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+    // The config file the problem is in
+    pub source: Option<PathBuf>,
+    // 1 based position across every [[directory]] rule, None for problems outside a directory rule
+    pub rule: Option<usize>,
+    pub field: String,
+    pub kind: ConfigErrorKind,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(rule: Option<usize>, field: &str, kind: ConfigErrorKind, message: String) -> ConfigError {
+        ConfigError { source: None, rule, field: field.to_string(), kind, message }
+    }
+
+    pub fn in_file(mut self, source: &Path) -> ConfigError {
+        self.source = Some(source.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(source) = &self.source {
+            write!(f, "'{}' ", source.display())?;
+        }
+        match self.rule {
+            Some(rule) => write!(f, "[[directory]] rule {} '{}': {}", rule, self.field, self.message),
+            None if self.field.is_empty() => write!(f, "{}", self.message),
+            None => write!(f, "'{}': {}", self.field, self.message),
+        }
+    }
+}
+
+// Pull the field name out of a serde error such as "missing field `path`" or "... in `retentionindays`"
+fn error_field(message: &str) -> String {
+    let field = message.rsplit_once("in `").or_else(|| message.rsplit_once("field `"))
+        .and_then(|(_, rest)| rest.split_once('`'))
+        .map(|(field, _)| field.to_string());
+    field.unwrap_or_default()
+}
+
+fn first_line(message: &str) -> String {
+    message.lines().next().unwrap_or_default().to_string()
+}
+
+// Expand placeholders in one setting, reporting any variable that is not set
+fn expand_field(value: &mut String, rule: Option<usize>, field: &str, errors: &mut Vec<ConfigError>) {
+    let mut undefined = Vec::new();
+    *value = placeholders::expand(value, &mut undefined);
+    for name in undefined {
+        errors.push(ConfigError::new(rule, field, ConfigErrorKind::UndefinedVariable, format!("environment variable '{}' is not set", name)));
+    }
+}
+
+impl Application {
+    fn expand_placeholders(&mut self, errors: &mut Vec<ConfigError>) {
+        if let Some(summaryfile) = &mut self.summaryfile {
+            expand_field(summaryfile, None, "application.summaryfile", errors);
+        }
+        if let Some(metricsfile) = &mut self.metricsfile {
+            expand_field(metricsfile, None, "application.metricsfile", errors);
+        }
+        if let Some(lockfile) = &mut self.lockfile {
+            expand_field(lockfile, None, "application.lockfile", errors);
+        }
+        if let Some(statefile) = &mut self.statefile {
+            expand_field(statefile, None, "application.statefile", errors);
+        }
+        for include in &mut self.include {
+            expand_field(include, None, "application.include", errors);
+        }
+    }
+}
+
+impl Directory {
+    // A rule with every optional setting left at its default, for building a Policy in code.
+    // Returns the same problems a config file rule with these settings would have
+    pub fn new(path: impl Into<String>, filenamecontains: impl Into<String>, retentionindays: u64) -> Result<Directory, Vec<ConfigError>> {
+        let settings = DirectorySettings {
+            path: Some(path.into()),
+            filenamecontains: Some(filenamecontains.into()),
+            retentionindays: Some(retentionindays),
+            ..DirectorySettings::default()
+        };
+        let mut errors = Vec::new();
+        let dir = settings.resolve(1, &mut errors);
+        if let Some(dir) = &dir {
+            validate_directory(1, dir, &mut errors);
+        }
+        match dir {
+            Some(dir) if errors.is_empty() => Ok(dir),
+            _ => Err(errors),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        rule_label(self.name.as_deref(), &self.path, &self.filenamecontains)
+    }
+
+    // Rules are matched up by name, or by path and filenamecontains for rules without one
+    pub fn key(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}|{}", self.path, self.filenamecontains),
+        }
+    }
+
+    fn expand_placeholders(&mut self, rule: usize, errors: &mut Vec<ConfigError>) {
+        expand_field(&mut self.path, Some(rule), "path", errors);
+        expand_field(&mut self.movetopath, Some(rule), "movetopath", errors);
+        if let Some(statusfile) = &mut self.statusfile {
+            expand_field(statusfile, Some(rule), "statusfile", errors);
+        }
+        if let Some(sftp) = &mut self.sftp {
+            expand_field(&mut sftp.privatekey, Some(rule), "sftp.privatekey", errors);
+            expand_field(&mut sftp.remotepath, Some(rule), "sftp.remotepath", errors);
+            if let Some(publickey) = &mut sftp.publickey {
+                expand_field(publickey, Some(rule), "sftp.publickey", errors);
+            }
+            if let Some(knownhosts) = &mut sftp.knownhosts {
+                expand_field(knownhosts, Some(rule), "sftp.knownhosts", errors);
+            }
+        }
+    }
+}
+
+fn check_keys(table: &Table, allowed: &[&str], rule: Option<usize>, prefix: &str, errors: &mut Vec<ConfigError>) {
+    for key in table.keys().filter(|key| !allowed.contains(&key.as_str())) {
+        errors.push(ConfigError::new(rule, &format!("{}{}", prefix, key), ConfigErrorKind::UnknownKey, format!("unknown key, expected one of {}", allowed.join(", "))));
+    }
+}
+
+fn deserialize_settings(table: &Table, prefix: &str, errors: &mut Vec<ConfigError>) -> DirectorySettings {
+    DirectorySettings::deserialize(Value::Table(table.clone())).unwrap_or_else(|e| {
+        let message = e.to_string();
+        errors.push(ConfigError::new(None, &format!("{}{}", prefix, error_field(&message)), ConfigErrorKind::InvalidValue, first_line(&message)));
+        DirectorySettings::default()
+    })
+}
+
+pub fn load_config(path: &Path) -> Result<ConfigFile, Vec<ConfigError>> {
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| vec![ConfigError::new(None, "", ConfigErrorKind::Read, format!("Failed to read: {}", e)).in_file(path)])?;
+    let mut root: Table = toml::from_str(&contents)
+        .map_err(|e| vec![ConfigError::new(None, "", ConfigErrorKind::Parse, format!("Failed to parse: {}", e)).in_file(path)])?;
+
+    // Older layouts are upgraded in memory before anything else is read
+    let version = migrate::config_version(&root)
+        .map_err(|message| vec![ConfigError::new(None, "version", ConfigErrorKind::OutOfRange, message).in_file(path)])?;
+    migrate::migrate(&mut root, version);
+
+    let mut errors = Vec::new();
+    let mut load_errors = Vec::new();
+    check_keys(&root, TOP_LEVEL_KEYS, None, "", &mut load_errors);
+
+    // Application settings
+    let application = match root.get("application") {
+        Some(Value::Table(table)) => {
+            check_keys(table, APPLICATION_KEYS, None, "application.", &mut load_errors);
+            Application::deserialize(Value::Table(table.clone()))
+                .map_err(|e| {
+                    let message = e.to_string();
+                    errors.push(ConfigError::new(None, &format!("application.{}", error_field(&message)), ConfigErrorKind::InvalidValue, first_line(&message)));
+                })
+                .ok()
+                .map(|mut application| {
+                    application.expand_placeholders(&mut load_errors);
+                    application
+                })
+        },
+        _ => {
+            errors.push(ConfigError::new(None, "application", ConfigErrorKind::InvalidValue, "missing [application] table".to_string()));
+            None
+        },
+    };
+
+    // Settings every directory rule falls back on
+    let defaults = match root.get("defaults") {
+        Some(Value::Table(table)) => {
+            check_keys(table, DEFAULTS_KEYS, None, "defaults.", &mut load_errors);
+            deserialize_settings(table, "defaults.", &mut errors)
+        },
+        Some(_) => {
+            errors.push(ConfigError::new(None, "defaults", ConfigErrorKind::InvalidValue, "should be a table".to_string()));
+            DirectorySettings::default()
+        },
+        None => DirectorySettings::default(),
+    };
+
+    // Named bundles of settings a rule can pick with profile = "name"
+    let mut profiles: HashMap<String, DirectorySettings> = HashMap::new();
+    match root.get("profiles") {
+        Some(Value::Table(table)) => {
+            for (name, profile) in table {
+                let prefix = format!("profiles.{}.", name);
+                match profile {
+                    Value::Table(profile) => {
+                        check_keys(profile, DEFAULTS_KEYS, None, &prefix, &mut load_errors);
+                        profiles.insert(name.clone(), deserialize_settings(profile, &prefix, &mut errors));
+                    },
+                    _ => errors.push(ConfigError::new(None, &format!("profiles.{}", name), ConfigErrorKind::InvalidValue, "should be a table".to_string())),
+                }
+            }
+        },
+        Some(_) => errors.push(ConfigError::new(None, "profiles", ConfigErrorKind::InvalidValue, "should be a table".to_string())),
+        None => (),
+    }
+
+    // Directory rules from the main file
+    let mut loader = RuleLoader { defaults, profiles, directory: Vec::new(), rule_count: 0, errors, load_errors };
+    match root.get("directory") {
+        Some(rules) => loader.load_rules(rules, path),
+        None if application.as_ref().is_some_and(|application| !application.include.is_empty()) => (),
+        None => loader.errors.push(ConfigError::new(None, "directory", ConfigErrorKind::InvalidValue, "missing [[directory]] rules".to_string())),
+    }
+    loader.set_source(0, 0, path);
+
+    // Directory rules from every included file
+    let mut files = vec![path.to_path_buf()];
+    let mut include_patterns = Vec::new();
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    for pattern in application.iter().flat_map(|application| &application.include) {
+
+        // Relative patterns are relative to the main config file
+        let full_pattern = base_dir.join(pattern).to_string_lossy().to_string();
+        include_patterns.push(full_pattern.clone());
+        let mut include_files: Vec<PathBuf> = match glob::glob(&full_pattern) {
+            Ok(paths) => paths.filter_map(|entry| entry.ok()).collect(),
+            Err(e) => {
+                loader.errors.push(ConfigError::new(None, "application.include", ConfigErrorKind::InvalidValue, format!("'{}' is not a valid pattern: {}", pattern, e)).in_file(path));
+                continue;
+            },
+        };
+        include_files.sort();
+
+        for include_file in include_files {
+            let (errors_start, load_errors_start) = (loader.errors.len(), loader.load_errors.len());
+            loader.load_include(&include_file);
+            loader.set_source(errors_start, load_errors_start, &include_file);
+            files.push(include_file);
+        }
+    }
+
+    let RuleLoader { directory, mut errors, load_errors, .. } = loader;
+    match application {
+        Some(application) if errors.is_empty() => Ok(ConfigFile { directories: Directories { directory }, application, version, files, include_patterns, load_errors }),
+        _ => {
+            errors.extend(load_errors);
+            Err(errors)
+        },
+    }
+}
+
+// Collects directory rules from the main file and every included file
+struct RuleLoader {
+    defaults: DirectorySettings,
+    profiles: HashMap<String, DirectorySettings>,
+    directory: Vec<Directory>,
+    // Rules are numbered across every file in the order they are read, including rules that fail to load
+    rule_count: usize,
+    errors: Vec<ConfigError>,
+    load_errors: Vec<ConfigError>,
+}
+
+impl RuleLoader {
+
+    // Mark the errors found since the given positions as coming from this file
+    fn set_source(&mut self, errors_start: usize, load_errors_start: usize, source: &Path) {
+        let new_errors = self.errors.iter_mut().skip(errors_start).chain(self.load_errors.iter_mut().skip(load_errors_start));
+        for error in new_errors {
+            error.source.get_or_insert_with(|| source.to_path_buf());
+        }
+    }
+
+    fn load_include(&mut self, path: &Path) {
+
+        let mut root: Table = match fs::read_to_string(path).map(|contents| toml::from_str(&contents)) {
+            Ok(Ok(root)) => root,
+            Ok(Err(e)) => return self.errors.push(ConfigError::new(None, "", ConfigErrorKind::Parse, format!("Failed to parse: {}", e))),
+            Err(e) => return self.errors.push(ConfigError::new(None, "", ConfigErrorKind::Read, format!("Failed to read: {}", e))),
+        };
+
+        // Included files carry their own version
+        match migrate::config_version(&root) {
+            Ok(version) => migrate::migrate(&mut root, version),
+            Err(message) => return self.errors.push(ConfigError::new(None, "version", ConfigErrorKind::OutOfRange, message)),
+        }
+
+        // Included files only add directory rules
+        check_keys(&root, INCLUDE_TOP_LEVEL_KEYS, None, "", &mut self.load_errors);
+        if let Some(rules) = root.get("directory") {
+            self.load_rules(rules, path);
+        }
+    }
+
+    // Read each directory rule on its own so every bad rule is reported
+    fn load_rules(&mut self, rules: &Value, source: &Path) {
+
+        let rules = match rules {
+            Value::Array(rules) => rules,
+            _ => return self.errors.push(ConfigError::new(None, "directory", ConfigErrorKind::InvalidValue, "should be an array of [[directory]] tables".to_string())),
+        };
+
+        for rule in rules {
+            self.rule_count += 1;
+            let rule_number = self.rule_count;
+
+            if let Value::Table(rule_table) = rule {
+                check_keys(rule_table, DIRECTORY_KEYS, Some(rule_number), "", &mut self.load_errors);
+                if let Some(Value::Table(sftp)) = rule_table.get("sftp") {
+                    check_keys(sftp, SFTP_KEYS, Some(rule_number), "sftp.", &mut self.load_errors);
+                }
+            }
+
+            let settings = match DirectorySettings::deserialize(rule.clone()) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    let message = e.to_string();
+                    self.errors.push(ConfigError::new(Some(rule_number), &error_field(&message), ConfigErrorKind::InvalidValue, first_line(&message)));
+                    continue;
+                },
+            };
+
+            // Rule settings win over the profile, which wins over [defaults]
+            let settings = match &settings.profile {
+                Some(name) => match self.profiles.get(name) {
+                    Some(profile) => settings.clone().or(profile),
+                    None => {
+                        self.errors.push(ConfigError::new(Some(rule_number), "profile", ConfigErrorKind::UnknownProfile, format!("'{}' is not defined in [profiles]", name)));
+                        continue;
+                    },
+                },
+                None => settings,
+            }.or(&self.defaults);
+
+            if let Some(mut dir) = settings.resolve(rule_number, &mut self.errors) {
+                dir.expand_placeholders(rule_number, &mut self.load_errors);
+                dir.source = source.to_path_buf();
+                self.directory.push(dir);
+            }
+        }
+    }
+}
+
+// Compare paths after resolving them when they exist, so 'C:\Logs\' and 'C:\Logs' are the same
+fn normalize(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).components().collect())
+}
+
+fn validate_application(application: &Application, errors: &mut Vec<ConfigError>) {
+
+    // Retentionindays should be between 1 and 365
+    if application.logretentionindays < 1 || application.logretentionindays > 365 {
+        errors.push(ConfigError::new(None, "application.logretentionindays", ConfigErrorKind::OutOfRange, format!("should be a number between 1-365 but is set to {}", application.logretentionindays)));
+    }
+
+    // workers should be between 1 and 64
+    if let Some(workers) = application.workers {
+        if !(1..=64).contains(&workers) {
+            errors.push(ConfigError::new(None, "application.workers", ConfigErrorKind::OutOfRange, format!("should be a number between 1-64 but is set to {}", workers)));
+        }
+    }
+
+    // maxruntime should be between 1 minute and a day
+    if let Some(maxruntime) = application.maxruntime {
+        if !(1..=1440).contains(&maxruntime) {
+            errors.push(ConfigError::new(None, "application.maxruntime", ConfigErrorKind::OutOfRange, format!("should be a number of minutes between 1-1440 but is set to {}", maxruntime)));
+        }
+    }
+
+    // maxfilesperrun should leave room for at least one file
+    if application.maxfilesperrun == Some(0) {
+        errors.push(ConfigError::new(None, "application.maxfilesperrun", ConfigErrorKind::OutOfRange, "should be a number of files above 0".to_string()));
+    }
+
+    // nice should be between -20 and 19
+    if let Some(nice) = application.nice {
+        if !(-20..=19).contains(&nice) {
+            errors.push(ConfigError::new(None, "application.nice", ConfigErrorKind::OutOfRange, format!("should be a number between -20-19 but is set to {}", nice)));
+        }
+    }
+
+    // ionice should be a class with an optional level
+    if let Some(ionice) = &application.ionice {
+        if let Err(e) = priority::parse_ionice(ionice) {
+            errors.push(ConfigError::new(None, "application.ionice", ConfigErrorKind::InvalidValue, e));
+        }
+    }
+
+    // schedule should be a cron expression
+    if let Some(schedule) = &application.schedule {
+        if let Err(e) = schedule::parse_schedule(schedule) {
+            errors.push(ConfigError::new(None, "application.schedule", ConfigErrorKind::InvalidValue, format!("'{}' is not a valid cron schedule: {}", schedule, e)));
+        }
+    }
+}
+
+pub(crate) fn validate_directory(rule: usize, dir: &Directory, errors: &mut Vec<ConfigError>) {
+    let rule = Some(rule);
+
+    // path should be a directory
+    if !Path::new(&dir.path).is_dir() {
+        errors.push(ConfigError::new(rule, "path", ConfigErrorKind::NotADirectory, format!("should be an existing directory but is set to '{}'", dir.path)));
+    }
+
+    // name is optional but should be usable as a metrics label and with --only
+    if let Some(name) = &dir.name {
+        if name.is_empty() {
+            errors.push(ConfigError::new(rule, "name", ConfigErrorKind::Blank, "should not be blank".to_string()));
+        } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+            errors.push(ConfigError::new(rule, "name", ConfigErrorKind::InvalidCharacter, format!("should only contain letters, numbers, '-', '_' or '.' but is set to '{}'", name)));
+        } else if name.parse::<usize>().is_ok() {
+            errors.push(ConfigError::new(rule, "name", ConfigErrorKind::InvalidCharacter, format!("should not be only a number, --only {} picks rule {} by position", name, name)));
+        }
+    }
+
+    // filenamecontains should not be blank
+    if dir.filenamecontains.is_empty() {
+        errors.push(ConfigError::new(rule, "filenamecontains", ConfigErrorKind::Blank, "should not be blank".to_string()));
+    }
+
+    // filenamecontains should not contain a '_'
+    if dir.filenamecontains.contains('_') {
+        errors.push(ConfigError::new(rule, "filenamecontains", ConfigErrorKind::InvalidCharacter, format!("should not contain a '_' but is set to '{}'", dir.filenamecontains)));
+    }
+
+    // Retentionindays should be between 1 and 365
+    if dir.retentionindays < 1 || dir.retentionindays > 365 {
+        errors.push(ConfigError::new(rule, "retentionindays", ConfigErrorKind::OutOfRange, format!("should be a number between 1-365 but is set to {}", dir.retentionindays)));
+    }
+
+    // schedule should be a cron expression
+    if let Some(schedule) = &dir.schedule {
+        if let Err(e) = schedule::parse_schedule(schedule) {
+            errors.push(ConfigError::new(rule, "schedule", ConfigErrorKind::InvalidValue, format!("'{}' is not a valid cron schedule: {}", schedule, e)));
+        }
+    }
+
+    // maxreadrate and maxwriterate should leave room to make progress
+    for (field, rate) in [("maxreadrate", dir.limits.maxreadrate), ("maxwriterate", dir.limits.maxwriterate)] {
+        if rate == Some(0) {
+            errors.push(ConfigError::new(rule, field, ConfigErrorKind::OutOfRange, "should be a number of bytes per second above 0".to_string()));
+        }
+    }
+
+    // retryattempts counts the first try, retrybackoff is in milliseconds and doubles after each try
+    if !(1..=10).contains(&dir.retry.attempts) {
+        errors.push(ConfigError::new(rule, "retryattempts", ConfigErrorKind::OutOfRange, format!("should be a number between 1-10 but is set to {}", dir.retry.attempts)));
+    }
+    if dir.retry.backoff > Duration::from_secs(60) {
+        errors.push(ConfigError::new(rule, "retrybackoff", ConfigErrorKind::OutOfRange, format!("should be a number of milliseconds between 0-60000 but is set to {}", dir.retry.backoff.as_millis())));
+    }
+
+    // quietperiod should be between 1 second and 1 day
+    if let Some(quietperiod) = dir.active.quietperiod {
+        if !(1..=86400).contains(&quietperiod) {
+            errors.push(ConfigError::new(rule, "quietperiod", ConfigErrorKind::OutOfRange, format!("should be a number of seconds between 1-86400 but is set to {}", quietperiod)));
+        }
+    }
+
+    // movetopath should not be the same as path or inside it
+    if !dir.movetopath.is_empty() {
+        let path = normalize(&dir.path);
+        let movetopath = normalize(&dir.movetopath);
+        if movetopath == path {
+            errors.push(ConfigError::new(rule, "movetopath", ConfigErrorKind::DestinationInsidePath, format!("should not be the same as path '{}'", dir.path)));
+        } else if movetopath.starts_with(&path) {
+            errors.push(ConfigError::new(rule, "movetopath", ConfigErrorKind::DestinationInsidePath, format!("should not be inside path '{}'", dir.path)));
+        }
+    }
+
+    if let Some(sftp) = &dir.sftp {
+
+        // sftp takes the place of movetopath, so only one of them should be set
+        if !dir.movetopath.is_empty() {
+            errors.push(ConfigError::new(rule, "sftp", ConfigErrorKind::ConflictingSettings, "should not be set together with movetopath".to_string()));
+        }
+
+        for (field, value) in [("sftp.host", &sftp.host), ("sftp.username", &sftp.username), ("sftp.privatekey", &sftp.privatekey), ("sftp.remotepath", &sftp.remotepath)] {
+            if value.is_empty() {
+                errors.push(ConfigError::new(rule, field, ConfigErrorKind::Blank, "should not be blank".to_string()));
+            }
+        }
+
+        // The host key has to be checked unless that is turned off on purpose
+        if sftp.knownhosts.is_none() && !sftp.skiphostkeycheck {
+            errors.push(ConfigError::new(rule, "sftp.knownhosts", ConfigErrorKind::Missing, "should be set so the host key can be verified, or set sftp.skiphostkeycheck = true".to_string()));
+        }
+    }
+}
+
+// Two rules on the same path overlap when one filenamecontains also matches the other's files
+pub(crate) fn validate_overlaps(directories: &[Directory], errors: &mut Vec<ConfigError>) {
+    for (later, dir) in directories.iter().enumerate() {
+        for (earlier, other) in directories.iter().enumerate().take(later) {
+            if dir.name.is_some() && dir.name == other.name {
+                errors.push(ConfigError::new(Some(later + 1), "name", ConfigErrorKind::DuplicateRule,
+                    format!("'{}' is already used by rule {} from '{}'", dir.name.as_deref().unwrap_or_default(), earlier + 1, other.source.display())));
+            }
+
+            if normalize(&dir.path) != normalize(&other.path) {
+                continue;
+            }
+
+            if dir.filenamecontains == other.filenamecontains {
+                errors.push(ConfigError::new(Some(later + 1), "filenamecontains", ConfigErrorKind::DuplicateRule,
+                    format!("'{}' duplicates rule {} from '{}'", dir.filenamecontains, earlier + 1, other.source.display())));
+            } else if dir.filenamecontains.contains(&other.filenamecontains) || other.filenamecontains.contains(&dir.filenamecontains) {
+                errors.push(ConfigError::new(Some(later + 1), "filenamecontains", ConfigErrorKind::OverlappingRules,
+                    format!("'{}' overlaps with rule {} '{}' from '{}' on the same path", dir.filenamecontains, earlier + 1, other.filenamecontains, other.source.display())));
+            }
+        }
+    }
+}
+
+// Check every setting in every rule and return all of the problems at once
+pub fn validate_config(config_file: &ConfigFile) -> Vec<ConfigError> {
+    let mut errors = config_file.load_errors.clone();
+
+    validate_application(&config_file.application, &mut errors);
+    validate_overlaps(&config_file.directories.directory, &mut errors);
+    for (index, dir) in config_file.directories.directory.iter().enumerate() {
+        validate_directory(index + 1, dir, &mut errors);
+    }
+
+    // Point each problem at the file it came from
+    for error in &mut errors {
+        if error.source.is_none() {
+            error.source = match error.rule {
+                Some(rule) => config_file.directories.directory.get(rule - 1).map(|dir| dir.source.clone()),
+                None => config_file.files.first().cloned(),
+            };
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own for each test with the config files written into it
+    struct TestConfig {
+        dir: PathBuf,
+    }
+
+    impl TestConfig {
+        fn new(name: &str) -> TestConfig {
+            let dir = std::env::temp_dir().join(format!("logrc-config-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("logs")).unwrap();
+            TestConfig { dir }
+        }
+
+        fn logs(&self) -> String {
+            self.dir.join("logs").to_string_lossy().to_string()
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents.replace("LOGS", &self.logs())).unwrap();
+            path
+        }
+
+        fn load(&self, contents: &str) -> Result<ConfigFile, Vec<ConfigError>> {
+            load_config(&self.write("LogRC.toml", contents))
+        }
+
+        fn load_errors(&self, contents: &str) -> Vec<ConfigError> {
+            self.load(contents).err().expect("the config should fail to load")
+        }
+    }
+
+    impl Drop for TestConfig {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn kinds(errors: &[ConfigError]) -> Vec<(Option<usize>, String, ConfigErrorKind)> {
+        errors.iter().map(|error| (error.rule, error.field.clone(), error.kind)).collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let test = TestConfig::new("valid");
+        let config_file = test.load("version = 2\n[application]\nlogretentionindays = 3\n[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"app\"\nretentionindays = 7\n").unwrap();
+        assert!(validate_config(&config_file).is_empty());
+        assert_eq!(config_file.directories.directory[0].retentionindays, 7);
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let test = TestConfig::new("problems");
+        let config_file = test.load(r#"
+version = 2
+[application]
+logretentionindays = 0
+workers = 100
+[[directory]]
+path = "LOGS/missing"
+filenamecontains = "app_log"
+retentionindays = 400
+[[directory]]
+path = "LOGS"
+filenamecontains = "web"
+retentionindays = 7
+movetopath = "LOGS/archive"
+retryattempts = 0
+"#).unwrap();
+
+        assert_eq!(kinds(&validate_config(&config_file)), [
+            (None, "application.logretentionindays".to_string(), ConfigErrorKind::OutOfRange),
+            (None, "application.workers".to_string(), ConfigErrorKind::OutOfRange),
+            (Some(1), "path".to_string(), ConfigErrorKind::NotADirectory),
+            (Some(1), "filenamecontains".to_string(), ConfigErrorKind::InvalidCharacter),
+            (Some(1), "retentionindays".to_string(), ConfigErrorKind::OutOfRange),
+            (Some(2), "retryattempts".to_string(), ConfigErrorKind::OutOfRange),
+            (Some(2), "movetopath".to_string(), ConfigErrorKind::DestinationInsidePath),
+        ]);
+    }
+
+    #[test]
+    fn bad_values_and_missing_settings_fail_to_load() {
+        let test = TestConfig::new("load");
+        let errors = test.load_errors("version = 2\n[application]\nlogretentionindays = 3\n[[directory]]\npath = \"LOGS\"\nretentionindays = \"seven\"\n[[directory]]\npath = \"LOGS\"\n");
+        assert_eq!(kinds(&errors), [
+            (Some(1), "retentionindays".to_string(), ConfigErrorKind::InvalidValue),
+            (Some(2), "filenamecontains".to_string(), ConfigErrorKind::Missing),
+            (Some(2), "retentionindays".to_string(), ConfigErrorKind::Missing),
+        ]);
+
+        let errors = test.load_errors("[application\n");
+        assert_eq!(kinds(&errors), [(None, String::new(), ConfigErrorKind::Parse)]);
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_without_stopping_the_load() {
+        let test = TestConfig::new("unknown");
+        let config_file = test.load("version = 2\n[application]\nlogretentionindays = 3\nlogretention = 4\n[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"app\"\nretentionindays = 7\ncompres = true\n").unwrap();
+        assert_eq!(kinds(&validate_config(&config_file)), [
+            (None, "application.logretention".to_string(), ConfigErrorKind::UnknownKey),
+            (Some(1), "compres".to_string(), ConfigErrorKind::UnknownKey),
+        ]);
+    }
+
+    #[test]
+    fn overlapping_rules_on_one_path_are_reported() {
+        let test = TestConfig::new("overlap");
+        let config_file = test.load(r#"
+version = 2
+[application]
+logretentionindays = 3
+[[directory]]
+path = "LOGS"
+filenamecontains = "app"
+retentionindays = 7
+[[directory]]
+path = "LOGS/"
+filenamecontains = "app"
+retentionindays = 7
+[[directory]]
+path = "LOGS"
+filenamecontains = "myapp"
+retentionindays = 7
+"#).unwrap();
+
+        assert_eq!(kinds(&validate_config(&config_file)), [
+            (Some(2), "filenamecontains".to_string(), ConfigErrorKind::DuplicateRule),
+            (Some(3), "filenamecontains".to_string(), ConfigErrorKind::OverlappingRules),
+            (Some(3), "filenamecontains".to_string(), ConfigErrorKind::OverlappingRules),
+        ]);
+    }
+
+    #[test]
+    fn rules_fall_back_on_their_profile_then_defaults() {
+        let test = TestConfig::new("profiles");
+        let config_file = test.load(r#"
+version = 2
+[application]
+logretentionindays = 3
+[defaults]
+retentionindays = 30
+compress = true
+[profiles.iis]
+filenamecontains = "ex"
+retentionindays = 14
+[[directory]]
+path = "LOGS"
+profile = "iis"
+[[directory]]
+path = "LOGS"
+profile = "iis"
+filenamecontains = "web"
+compress = false
+[[directory]]
+path = "LOGS"
+filenamecontains = "app"
+"#).unwrap();
+
+        let rules: Vec<(&str, u64, bool)> = config_file.directories.directory.iter()
+            .map(|dir| (dir.filenamecontains.as_str(), dir.retentionindays, dir.compress))
+            .collect();
+        assert_eq!(rules, [("ex", 14, true), ("web", 14, false), ("app", 30, true)]);
+    }
+
+    #[test]
+    fn unknown_profile_is_reported() {
+        let test = TestConfig::new("unknown-profile");
+        let errors = test.load_errors("version = 2\n[application]\nlogretentionindays = 3\n[[directory]]\npath = \"LOGS\"\nprofile = \"nope\"\n");
+        assert_eq!(kinds(&errors), [(Some(1), "profile".to_string(), ConfigErrorKind::UnknownProfile)]);
+    }
+
+    #[test]
+    fn included_files_add_rules_in_name_order() {
+        let test = TestConfig::new("include");
+        test.write("conf.d/b.toml", "[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"web\"\nretentionindays = 7\n");
+        test.write("conf.d/a.toml", "[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"api\"\nretentionindays = 7\n");
+        test.write("conf.d/notes.txt", "not a config file");
+        let config_file = test.load("version = 2\n[application]\nlogretentionindays = 3\ninclude = [\"conf.d/*.toml\"]\n[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"app\"\nretentionindays = 7\n").unwrap();
+
+        let rules: Vec<(&str, PathBuf)> = config_file.directories.directory.iter()
+            .map(|dir| (dir.filenamecontains.as_str(), dir.source.strip_prefix(&test.dir).unwrap().to_path_buf()))
+            .collect();
+        assert_eq!(rules, [("app", PathBuf::from("LogRC.toml")), ("api", PathBuf::from("conf.d/a.toml")), ("web", PathBuf::from("conf.d/b.toml"))]);
+        assert_eq!(config_file.files.len(), 3);
+        assert_eq!(config_file.include_patterns, [test.dir.join("conf.d/*.toml").to_string_lossy()]);
+        assert!(validate_config(&config_file).is_empty());
+    }
+
+    #[test]
+    fn problems_in_included_files_point_at_the_file() {
+        let test = TestConfig::new("include-errors");
+        let include = test.write("conf.d/a.toml", "[application]\nworkers = 2\n[[directory]]\npath = \"LOGS\"\nfilenamecontains = \"a_b\"\nretentionindays = 7\n");
+        let config_file = test.load("version = 2\n[application]\nlogretentionindays = 3\ninclude = [\"conf.d/*.toml\"]\n").unwrap();
+
+        let errors = validate_config(&config_file);
+        assert_eq!(kinds(&errors), [
+            (None, "application".to_string(), ConfigErrorKind::UnknownKey),
+            (Some(1), "filenamecontains".to_string(), ConfigErrorKind::InvalidCharacter),
+        ]);
+        assert!(errors.iter().all(|error| error.source.as_deref() == Some(include.as_path())));
+    }
+}
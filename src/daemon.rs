@@ -0,0 +1,222 @@
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use log::{info, error};
+use std::{path::Path, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::{Duration, Instant}};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::{AppError, ExitStatus, LOG_NAME, endtasks, has_rule_errors, log_directory, no_matching_rules, starttask, state, take_lock};
+use crate::cli::{DaemonArgs, RuleSelection};
+use crate::reload::{self, ConfigWatch};
+use crate::watch::FileWatch;
+use log_rc::{ConfigError, ConfigFile, Directory, Engine, LogObserver, Policy, RetryPolicy, Summary, parse_schedule, remove_old_files, validate_config};
+
+// Used when neither the rule nor [application] sets a schedule, every day at midnight
+pub const DEFAULT_SCHEDULE: &str = "0 0 * * *";
+
+// How often the daemon wakes up to check for a stop signal
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// The schedule a rule runs on, falling back on [application]schedule and then the built in default
+pub fn rule_schedule<'a>(dir: &'a Directory, config_file: &'a ConfigFile) -> &'a str {
+    dir.schedule.as_deref()
+        .or(config_file.application.schedule.as_deref())
+        .unwrap_or(DEFAULT_SCHEDULE)
+}
+
+struct ScheduledRule {
+    dir: Directory,
+    schedule: Schedule,
+    next_run: Option<DateTime<Local>>,
+}
+
+impl ScheduledRule {
+    fn plan_next_run(&mut self, after: &DateTime<Local>) {
+        self.next_run = self.schedule.after(after).next();
+        match self.next_run {
+            Some(next_run) => info!("Next run for {} at {}", self.dir.label(), next_run.to_rfc3339()),
+            None => info!("No more runs are scheduled for {}", self.dir.label()),
+        }
+    }
+}
+
+// Work out the schedule for every selected rule that has no config errors
+fn schedule_rules(config_file: &ConfigFile, selection: &RuleSelection, config_errors: &[ConfigError]) -> Vec<ScheduledRule> {
+    let now = Local::now();
+    let mut rules = Vec::new();
+
+    for (index, dir) in config_file.directories.directory.iter().enumerate() {
+        if !selection.selects(index, dir.name.as_deref(), &dir.path, &dir.filenamecontains) {
+            continue;
+        }
+
+        // Skip the rule if the config settings have problems, this includes a bad schedule
+        if has_rule_errors(config_errors, index) {
+            error!("Skipping {} because of config errors", dir.label());
+            continue;
+        }
+
+        let expression = rule_schedule(dir, config_file);
+        let schedule = match parse_schedule(expression) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                error!("Skipping {} because schedule '{}' is not valid: {}", dir.label(), expression, e);
+                continue;
+            },
+        };
+        info!("Scheduled {} with '{}'", dir.label(), expression);
+
+        let mut rule = ScheduledRule { dir: dir.clone(), schedule, next_run: None };
+        rule.plan_next_run(&now);
+        rules.push(rule);
+    }
+
+    rules
+}
+
+// Watch the rules that compress so rotated files do not wait for the next scheduled run
+fn start_file_watch(rules: &[ScheduledRule]) -> Option<FileWatch> {
+    let watched = rules.iter().map(|rule| rule.dir.clone()).filter(|dir| dir.compress).collect();
+    match FileWatch::start(watched) {
+        Ok(file_watch) => Some(file_watch),
+        Err(e) => {
+            error!("Failed to start watching for file changes, only the schedule will be used: {}", e);
+            None
+        },
+    }
+}
+
+fn start_config_watch(config_file: &ConfigFile) -> Option<ConfigWatch> {
+    match ConfigWatch::start(config_file) {
+        Ok(config_watch) => Some(config_watch),
+        Err(e) => {
+            error!("Failed to start watching the config files, send SIGHUP to reload: {}", e);
+            None
+        },
+    }
+}
+
+// Keep running and start each directory rule when its schedule is due, until SIGTERM or SIGINT.
+// The config is loaded again when one of its files changes or on SIGHUP
+pub fn daemon(config_path: &Path, config_file: ConfigFile, args: &DaemonArgs) -> Result<ExitStatus, AppError> {
+
+    let (selection, watch) = (&args.selection, args.watch);
+    let log_dir = log_directory(config_path);
+    let config_errors = validate_config(&config_file);
+
+    // Scheduled runs would race a one off run started at the same time, so the daemon holds the lock the whole time.
+    // It is taken before the log is opened, like a one off run
+    let lock = match take_lock(config_path, &config_file.application, &args.lock)? {
+        Some(lock) => lock,
+        None => return Ok(ExitStatus::Success),
+    };
+    starttask(&config_file.application, &log_dir, &config_errors)?;
+    lock.log_taken();
+
+    // Stop between runs rather than part way through one
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&stop)) {
+            error!("Failed to register the handler for signal {}: {}", signal, e);
+        }
+    }
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload_requested)) {
+        error!("Failed to register the handler for SIGHUP: {}", e);
+    }
+
+    let mut config_file = config_file;
+    let mut rules = schedule_rules(&config_file, selection, &config_errors);
+    if rules.is_empty() {
+        if !config_file.directories.directory.iter().enumerate().any(|(index, dir)| selection.selects(index, dir.name.as_deref(), &dir.path, &dir.filenamecontains)) {
+            return Err(AppError::Config(vec![no_matching_rules(selection)]));
+        }
+        error!("No directory rules can be scheduled");
+        return Ok(ExitStatus::TotalFailure);
+    }
+
+    let mut file_watch = if watch { start_file_watch(&rules) } else { None };
+    let mut config_watch = start_config_watch(&config_file);
+
+    let engine = Engine::new();
+    let mut today = Local::now().date_naive();
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(POLL_INTERVAL);
+        let now = Local::now();
+
+        // Swap in the new rules only once the new config has loaded and validated
+        let config_changed = config_watch.as_mut().is_some_and(ConfigWatch::changed);
+        if reload_requested.swap(false, Ordering::Relaxed) || config_changed {
+            if let Some(new_config) = reload::reload(config_path, &config_file, selection) {
+                config_file = new_config;
+                rules = schedule_rules(&config_file, selection, &[]);
+                file_watch = if watch { start_file_watch(&rules) } else { None };
+                config_watch = start_config_watch(&config_file);
+            }
+        }
+
+        // The log file moves to a new day by itself, old ones still need clearing out
+        if now.date_naive() != today {
+            today = now.date_naive();
+            if let Err(e) = remove_old_files(&log_dir.to_string_lossy(), LOG_NAME, &config_file.application.logretentionindays, &RetryPolicy::default(), &LogObserver, false) {
+                error!("Failed to remove application logs past retention: {}", e);
+            }
+        }
+
+        for (dir, files) in file_watch.as_mut().map(FileWatch::quiet_files).unwrap_or_default() {
+            info!("{} watched file(s) stopped changing for {}", files.len(), dir.label());
+            let outcome = engine.compress(&dir, &files);
+            Summary::from_outcome(&outcome).log(&dir.label());
+        }
+
+        let due: Vec<usize> = rules.iter().enumerate()
+            .filter(|(_, rule)| rule.next_run.is_some_and(|next_run| next_run <= now))
+            .map(|(position, _)| position)
+            .collect();
+        if due.is_empty() {
+            continue;
+        }
+
+        let start_time = Instant::now();
+        for position in &due {
+            info!("Starting scheduled run for {}", rules[*position].dir.label());
+        }
+
+        // Each batch gets its own budget, rules it leaves out wait for their next scheduled run
+        let state_path = state::state_path(config_path, &config_file.application);
+        let run_state = state::load(&state_path);
+        let mut builder = Policy::builder()
+            .application(&config_file.application)
+            .rules(due.iter().map(|position| rules[*position].dir.clone()))
+            .pending_archives(run_state.pendingarchives);
+
+        // Carry on from the rule the last run stopped on when it is part of this batch,
+        // otherwise that rule stays the place to resume from unless this batch stops part way itself
+        let (resume_from, elsewhere) = state::resume_point(run_state.nextrule, due.iter().map(|position| &rules[*position].dir));
+        if let Some(resume_from) = resume_from {
+            builder = builder.resume_from(resume_from);
+        }
+        let summaries = match builder.build() {
+            Ok(policy) => {
+                let report = engine.run(&policy);
+                state::save(&state_path, &state::RunState { nextrule: report.next_rule.or(elsewhere), pendingarchives: report.pending_archives });
+                report.rules.into_iter().map(|rule_report| rule_report.summary).collect()
+            },
+            Err(config_errors) => {
+                for config_error in config_errors {
+                    error!("Skipping the scheduled run because of config errors: {}", config_error);
+                }
+                Vec::new()
+            },
+        };
+
+        let finished = Local::now();
+        for position in due {
+            rules[position].plan_next_run(&finished);
+        }
+        endtasks(start_time, now, summaries, &config_file.application);
+    }
+
+    info!("Received a stop signal, shutting down");
+    Ok(ExitStatus::Success)
+}
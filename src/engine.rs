@@ -0,0 +1,351 @@
+use chrono::Local;
+use log::{info, warn};
+use std::{fs, path::{Path, PathBuf}, time::Duration};
+
+use crate::config::{validate_directory, validate_overlaps};
+use crate::workers::process_rules;
+use crate::{Application, ConfigError, ConfigErrorKind, Directory, DirectorySummary, LogObserver, LogRcError, Observer, Outcome, PendingArchive, RunBudget, StatusRecord, Summary,
+    archive_stats, compress_files, file_created_date, latest_zip_path, create_status_file, default_status_file_name, group_and_compress_files, move_files_except_today, move_files_to_sftp,
+    remove_old_files, remove_old_sftp_files};
+
+// The rules to run and the limits to run them under, built from a config file or in code
+pub struct Policy {
+    rules: Vec<Directory>,
+    workers: usize,
+    max_runtime: Option<Duration>,
+    max_files: Option<u64>,
+    pending_archives: Vec<PendingArchive>,
+    resume_from: Option<String>,
+    dry_run: bool,
+}
+
+impl Policy {
+    pub fn builder() -> PolicyBuilder {
+        PolicyBuilder::default()
+    }
+
+    pub fn rules(&self) -> &[Directory] {
+        &self.rules
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+pub struct PolicyBuilder {
+    policy: Policy,
+}
+
+impl Default for PolicyBuilder {
+    fn default() -> PolicyBuilder {
+        PolicyBuilder {
+            policy: Policy {
+                rules: Vec::new(),
+                workers: 1,
+                max_runtime: None,
+                max_files: None,
+                pending_archives: Vec::new(),
+                resume_from: None,
+                dry_run: false,
+            },
+        }
+    }
+}
+
+impl PolicyBuilder {
+    pub fn rule(mut self, dir: Directory) -> PolicyBuilder {
+        self.policy.rules.push(dir);
+        self
+    }
+
+    pub fn rules(mut self, rules: impl IntoIterator<Item = Directory>) -> PolicyBuilder {
+        self.policy.rules.extend(rules);
+        self
+    }
+
+    // Take workers, maxruntime and maxfilesperrun from [application]
+    pub fn application(self, application: &Application) -> PolicyBuilder {
+        let builder = self.workers(application.workers());
+        let builder = match application.max_runtime() {
+            Some(max_runtime) => builder.max_runtime(max_runtime),
+            None => builder,
+        };
+        match application.maxfilesperrun {
+            Some(max_files) => builder.max_files(max_files),
+            None => builder,
+        }
+    }
+
+    pub fn workers(mut self, workers: usize) -> PolicyBuilder {
+        self.policy.workers = workers;
+        self
+    }
+
+    pub fn max_runtime(mut self, max_runtime: Duration) -> PolicyBuilder {
+        self.policy.max_runtime = Some(max_runtime);
+        self
+    }
+
+    pub fn max_files(mut self, max_files: u64) -> PolicyBuilder {
+        self.policy.max_files = Some(max_files);
+        self
+    }
+
+    // Archives an earlier run left part way through, so this run adds to them rather than starting new ones
+    pub fn pending_archives(mut self, pending: Vec<PendingArchive>) -> PolicyBuilder {
+        self.policy.pending_archives = pending;
+        self
+    }
+
+    // Start with the rule with this key, see Directory::key. The rules before it go last
+    pub fn resume_from(mut self, key: impl Into<String>) -> PolicyBuilder {
+        self.policy.resume_from = Some(key.into());
+        self
+    }
+
+    // Work out what every rule would do without changing anything on disk
+    pub fn dry_run(mut self, dry_run: bool) -> PolicyBuilder {
+        self.policy.dry_run = dry_run;
+        self
+    }
+
+    // Check the rules the same way a config file is checked, rule numbers count from 1 in the order they were added
+    pub fn build(self) -> Result<Policy, Vec<ConfigError>> {
+        let policy = self.policy;
+        let mut errors = Vec::new();
+
+        validate_overlaps(&policy.rules, &mut errors);
+        for (index, dir) in policy.rules.iter().enumerate() {
+            validate_directory(index + 1, dir, &mut errors);
+        }
+
+        if !(1..=64).contains(&policy.workers) {
+            errors.push(ConfigError::new(None, "workers", ConfigErrorKind::OutOfRange, format!("should be a number between 1-64 but is set to {}", policy.workers)));
+        }
+        if policy.max_files == Some(0) {
+            errors.push(ConfigError::new(None, "maxfilesperrun", ConfigErrorKind::OutOfRange, "should be a number of files above 0".to_string()));
+        }
+
+        if errors.is_empty() { Ok(policy) } else { Err(errors) }
+    }
+}
+
+// Everything a directory rule did, or would do in a dry run
+pub struct RuleReport {
+    // Position of the rule in the policy
+    pub rule: usize,
+    pub summary: DirectorySummary,
+    pub outcome: Outcome,
+    pub status_file: Option<PathBuf>,
+    // False when the run budget ran out during the rule, so it may have left work for the next run
+    pub complete: bool,
+}
+
+pub struct Report {
+    // The rules that ran, in the order they were started. Rules left out because the budget ran out are missing
+    pub rules: Vec<RuleReport>,
+    // The first rule that was left out or did not finish, for Policy::resume_from on the next run
+    pub next_rule: Option<String>,
+    // Archives left part way through a date group, for Policy::pending_archives on the next run
+    pub pending_archives: Vec<PendingArchive>,
+}
+
+impl Report {
+    pub fn failed_rules(&self) -> usize {
+        self.rules.iter().filter(|report| report.summary.summary.errors > 0).count()
+    }
+}
+
+// Runs the retention, compression and move steps for each rule in a policy
+pub struct Engine {
+    observer: Box<dyn Observer>,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    // An engine that logs what it does
+    pub fn new() -> Engine {
+        Engine::with_observer(LogObserver)
+    }
+
+    // An engine that reports what it does to the observer in place of the log
+    pub fn with_observer(observer: impl Observer + 'static) -> Engine {
+        Engine { observer: Box::new(observer) }
+    }
+
+    pub fn run(&self, policy: &Policy) -> Report {
+
+        // A dry run gets no limits, but plans to add to the archives the last run left unfinished like the real run would
+        let budget = if policy.dry_run {
+            RunBudget::new(None, None, policy.pending_archives.clone())
+        } else {
+            RunBudget::new(policy.max_runtime, policy.max_files, policy.pending_archives.clone())
+        };
+
+        // Start the rules at the one the last run stopped on, the rules before it go last
+        let mut order: Vec<(usize, &Directory)> = policy.rules.iter().enumerate().collect();
+        if let Some(resume_from) = &policy.resume_from {
+            match order.iter().position(|(_, dir)| dir.key() == *resume_from) {
+                Some(position) => {
+                    if position > 0 {
+                        info!("Resuming with {} where the last run stopped", order[position].1.label());
+                    }
+                    order.rotate_left(position);
+                },
+                None => warn!("The rule '{}' the last run stopped on is no longer in the config, starting from the first rule", resume_from),
+            }
+        }
+
+        let rules = process_rules(self, &order, policy.workers, &budget, policy.dry_run);
+
+        // The next run starts with the first rule that was skipped or did not finish
+        let next_rule = order.iter()
+            .find(|(index, _)| !rules.iter().any(|report| report.rule == *index && report.complete))
+            .map(|(_, dir)| dir.key());
+
+        Report { rules, next_rule, pending_archives: budget.pending_archives() }
+    }
+
+    // Compress files that were seen to stop changing, outside of a scheduled run. Files turn up a few at a time,
+    // so each batch adds to the archive an earlier batch made for the same day rather than starting a -2 archive
+    pub fn compress(&self, dir: &Directory, files: &[PathBuf]) -> Outcome {
+        let mut pending: Vec<PendingArchive> = Vec::new();
+        for path in files {
+            let Some(directory) = path.parent() else { continue };
+            let Ok(date) = fs::metadata(path).and_then(|metadata| file_created_date(&metadata)) else { continue };
+            let date = date.format("%Y-%m-%d").to_string();
+            if let Some(archive) = latest_zip_path(&date, directory, &dir.filenamecontains) {
+                let archive = PendingArchive { directory: directory.to_path_buf(), filenamecontains: dir.filenamecontains.clone(), date, path: archive };
+                if !pending.contains(&archive) {
+                    pending.push(archive);
+                }
+            }
+        }
+
+        let mut outcome = Outcome::default();
+        let observer = self.observer.as_ref();
+        let budget = RunBudget::new(None, None, pending);
+        self.record_outcome(&mut outcome, compress_files(files, &dir.filenamecontains, &dir.active, &dir.limits, &dir.retry, &budget, observer, false), "Completed file compression", "There was an issue compressing the files", false);
+        outcome
+    }
+
+    pub(crate) fn observer(&self) -> &dyn Observer {
+        self.observer.as_ref()
+    }
+
+    // Run every step for a single directory rule and report what happened
+    pub(crate) fn run_rule(&self, rule: usize, dir: &Directory, budget: &RunBudget, dry_run: bool) -> RuleReport {
+
+        let started = Local::now();
+        let mut outcome = Outcome::default();
+        let observer = self.observer.as_ref();
+
+        // Remove old log files
+        observer.on_step_started(&format!("Removing files with a date modified older then {} days for FilePath '{}\\*{}*.[log|txt|zip]'", dir.retentionindays, dir.path, dir.filenamecontains));
+        self.record_outcome(&mut outcome, remove_old_files(&dir.path, &dir.filenamecontains, &dir.retentionindays, &dir.retry, observer, dry_run), "Completed file retention", "There was an issue removing the files", dry_run);
+
+        // Daily Compress log files
+        if dir.compress {
+            observer.on_step_started(&format!("Compressing files older then today for FilePath '{}\\*{}*.[log|txt]'", dir.path, dir.filenamecontains));
+            self.record_outcome(&mut outcome, group_and_compress_files(&dir.path, &dir.filenamecontains, &dir.active, &dir.limits, &dir.retry, budget, observer, dry_run), "Completed file compression", "There was an issue compressing the files", dry_run);
+        }else {
+            observer.on_step_skipped(&format!("Skipping File Compression for FilePath '{}\\*{}*.[log|txt]' because compress setting is false", dir.path, dir.filenamecontains));
+        }
+
+        // Upload to the SFTP destination in place of movetopath if it is set
+        let destination = if let Some(sftp) = &dir.sftp {
+
+            // Upload log files to the SFTP remotepath
+            observer.on_step_started(&format!("Uploading files to 'sftp://{}{}' older then today from FilePath '{}\\*{}*.[log|txt|zip]'", sftp.host, sftp.remotepath, dir.path, dir.filenamecontains));
+            self.record_outcome(&mut outcome, move_files_to_sftp(&dir.path, sftp, &dir.filenamecontains, &dir.active, &dir.retry, budget, observer, dry_run), "Completed file upload", "There was an issue uploading the files", dry_run);
+
+            // Remove old log files in the SFTP remotepath
+            observer.on_step_started(&format!("Removing files with a date modified older then {} days for FilePath 'sftp://{}{}/*{}*.[log|txt|zip]'", dir.retentionindays, sftp.host, sftp.remotepath, dir.filenamecontains));
+            self.record_outcome(&mut outcome, remove_old_sftp_files(sftp, &dir.filenamecontains, &dir.retentionindays, &dir.retry, observer, dry_run), "Completed remote file retention", "There was an issue removing the remote files", dry_run);
+
+            Some(format!("sftp://{}{}", sftp.host, sftp.remotepath))
+
+        // Move to path if it is set and exists
+        } else if Path::new(&dir.movetopath).is_dir() {
+
+            // Remove log files to movetopath
+            observer.on_step_started(&format!("Moving files to '{}' older then today from FilePath '{}\\*{}*.[log|txt|zip]'", dir.movetopath, dir.path, dir.filenamecontains));
+            self.record_outcome(&mut outcome, move_files_except_today(&dir.path, &dir.movetopath, &dir.filenamecontains, &dir.active, &dir.limits, &dir.retry, budget, observer, dry_run), "Completed file move", "There was an issue moving the files", dry_run);
+
+            // Remove old log files in movetopath
+            observer.on_step_started(&format!("Removing files with a date modified older then {} days for FilePath '{}\\*{}*.[log|txt|zip]'", dir.retentionindays, dir.movetopath, dir.filenamecontains));
+            self.record_outcome(&mut outcome, remove_old_files(&dir.movetopath, &dir.filenamecontains, &dir.retentionindays, &dir.retry, observer, dry_run), "Completed file retention", "There was an issue removing the files", dry_run);
+
+            Some(dir.movetopath.clone())
+
+        } else {
+            observer.on_step_skipped("Skipping moving logs to movetopath setting because directory does not exist or blank.");
+            None
+
+        };
+
+        // Count the archives kept for this directory
+        let mut archive_count = 0;
+        let mut archive_bytes = 0;
+        for archive_dir in [&dir.path, &dir.movetopath] {
+            if Path::new(archive_dir).is_dir() {
+                match archive_stats(archive_dir, &dir.filenamecontains) {
+                    Ok((count, bytes)) => {
+                        archive_count += count;
+                        archive_bytes += bytes;
+                    },
+                    Err(e) => observer.on_step_failed(&format!("There was an issue counting the archives in '{}'", archive_dir), &e),
+                }
+            }
+        }
+
+        let finished = Local::now();
+        let mut summary = DirectorySummary {
+            name: dir.name.clone(),
+            path: dir.path.clone(),
+            filenamecontains: dir.filenamecontains.clone(),
+            finished: finished.to_rfc3339(),
+            finished_timestamp: finished.timestamp(),
+            archive_count,
+            archive_bytes,
+            summary: Summary::from_outcome(&outcome),
+        };
+
+        // Write the status file once everything for this directory has finished
+        let mut status_file = None;
+        if let Some(destination) = destination {
+            let status_file_name = dir.statusfile.clone().unwrap_or_else(|| default_status_file_name(&dir.filenamecontains));
+            let record = StatusRecord::new(dir.name.as_deref(), &dir.path, &destination, &dir.filenamecontains, started.to_rfc3339(), finished.to_rfc3339(), outcome.clone());
+            match create_status_file(&dir.path, &status_file_name, dir.statusformat.unwrap_or_default(), &record, dry_run) {
+                Ok(path) => status_file = Some(path),
+                Err(e) => {
+                    observer.on_step_failed("There was an issue creating the status file", &e);
+                    summary.summary.errors += 1;
+                }
+            }
+        }
+
+        RuleReport { rule, summary, outcome, status_file, complete: dry_run || !budget.exhausted() }
+    }
+
+    // Report how an operation went and keep what it did for the status file
+    fn record_outcome(&self, outcome: &mut Outcome, result: Result<Outcome, LogRcError>, completed: &str, failed: &str, dry_run: bool) {
+        match result {
+            Ok(result) if dry_run => outcome.merge_planned(result),
+            Ok(result) => {
+                self.observer.on_step_finished(completed);
+                outcome.merge(result);
+            },
+            Err(e) => {
+                self.observer.on_step_failed(failed, &e);
+                outcome.errors.push(format!("{}: {}", failed, e));
+            },
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+use zip::result::ZipError;
+
+// Everything the library can fail with, so callers can match on the kind of problem rather than read the log
+#[derive(Debug)]
+pub enum LogRcError {
+    // A setting has a value that can not work, such as a key the SFTP server turns down
+    Config { setting: String, message: String },
+    // Reading, writing, moving or removing a file or directory failed
+    Io { path: PathBuf, source: io::Error },
+    // The zip library could not create or add to an archive
+    Archive { path: PathBuf, source: ZipError },
+    // A copy did not match the original, or a server could not be trusted
+    Verification { path: PathBuf, message: String },
+    // Another run holds the lock, with its PID when it could be read
+    Lock { path: PathBuf, pid: Option<u32> },
+}
+
+impl LogRcError {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> LogRcError {
+        LogRcError::Io { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn archive(path: impl AsRef<Path>, source: ZipError) -> LogRcError {
+        LogRcError::Archive { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn verification(path: impl AsRef<Path>, message: impl Into<String>) -> LogRcError {
+        LogRcError::Verification { path: path.as_ref().to_path_buf(), message: message.into() }
+    }
+
+    // What went wrong without the path, for messages that already name the file
+    pub fn reason(&self) -> String {
+        match self {
+            LogRcError::Io { source, .. } => source.to_string(),
+            LogRcError::Archive { source, .. } => source.to_string(),
+            LogRcError::Verification { message, .. } | LogRcError::Config { message, .. } => message.clone(),
+            LogRcError::Lock { .. } => self.to_string(),
+        }
+    }
+
+    // The underlying I/O error, used to decide if trying again could help
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            LogRcError::Io { source, .. } => Some(source),
+            LogRcError::Archive { source: ZipError::Io(source), .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogRcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogRcError::Config { setting, message } => write!(f, "'{}' {}", setting, message),
+            LogRcError::Io { path, source } => write!(f, "'{}': {}", path.display(), source),
+            LogRcError::Archive { path, source } => write!(f, "'{}': {}", path.display(), source),
+            LogRcError::Verification { path, message } => write!(f, "'{}': {}", path.display(), message),
+            LogRcError::Lock { path, pid: Some(pid) } => write!(f, "Another run (PID {}) holds the lock file '{}'", pid, path.display()),
+            LogRcError::Lock { path, pid: None } => write!(f, "Another run holds the lock file '{}'", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for LogRcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LogRcError::Io { source, .. } => Some(source),
+            LogRcError::Archive { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Attach the path an I/O error happened on
+pub(crate) trait WithPath<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T, LogRcError>;
+}
+
+impl<T> WithPath<T> for io::Result<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T, LogRcError> {
+        self.map_err(|source| LogRcError::io(path, source))
+    }
+}
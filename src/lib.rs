@@ -1,120 +1,418 @@
-use simplelog::*;
-use log::{info, error};
-use std::{fs::{self, File, OpenOptions, remove_file}, path::{Path, PathBuf}, io::{Write, ErrorKind}};
+use std::{fs::{self, File, OpenOptions}, path::{Path, PathBuf}, time::{Duration, SystemTime}};
 use chrono::*;
 use walkdir::WalkDir;
-use zip::write::{SimpleFileOptions, ZipWriter};
-use std::collections::HashMap;
+use zip::{ZipArchive, write::{SimpleFileOptions, ZipWriter}};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
 use filetime::FileTime;
 
-pub fn group_and_compress_files(dir_path: &str, search_string: &str) -> std::io::Result<()> {
-    let mut file_groups: HashMap<String, (PathBuf, Vec<PathBuf>, DateTime<FixedOffset>)> = HashMap::new();
-    let today = Local::now().date_naive();
+use crate::error::WithPath;
+
+mod active;
+mod budget;
+mod config;
+mod engine;
+mod error;
+mod metrics;
+mod migrate;
+mod observer;
+mod outcome;
+mod placeholders;
+mod priority;
+mod retry;
+mod schedule;
+mod sftp;
+mod status;
+mod summary;
+mod throttle;
+mod workers;
+pub use active::{ActiveFileCheck, ActiveFiles, OpenFileCheck};
+pub use budget::{PendingArchive, RunBudget};
+pub use config::{Application, ConfigError, ConfigErrorKind, ConfigFile, Directories, Directory, load_config, validate_config};
+pub use engine::{Engine, Policy, PolicyBuilder, Report, RuleReport};
+pub use error::LogRcError;
+pub use metrics::write_metrics_file;
+pub use migrate::{CURRENT_VERSION, migrate_file};
+pub use observer::{LogObserver, Observer};
+pub use outcome::{Outcome, FileRecord, ArchiveRecord, MoveRecord, FailedRecord};
+pub use priority::{IoClass, apply_priority, parse_ionice};
+pub use retry::{RetryError, RetryPolicy, TransientError, DEFAULT_RETRY_ATTEMPTS, DEFAULT_RETRY_BACKOFF, DEFAULT_RETRY_ON};
+pub use schedule::parse_schedule;
+pub use sftp::{SftpDestination, move_files_to_sftp, remove_old_sftp_files};
+pub use status::{StatusFormat, StatusRecord, create_status_file, default_status_file_name};
+pub use summary::{Summary, DirectorySummary, RunSummary, rule_label, write_summary_file};
+pub use throttle::{IoLimits, ThrottledReader, ThrottledWriter};
+pub use workers::RuleLogger;
+
+// Get the local date a file was created on
+pub(crate) fn file_created_date(metadata: &fs::Metadata) -> std::io::Result<NaiveDate> {
+    let created: DateTime<Utc> = metadata.created()?.into();
+
+    // Convert to local time with offset
+    let local_time = created.with_timezone(&Local);
+    let offset = local_time.offset().fix();
+    Ok(created.with_timezone(&offset).date_naive())
+}
 
-    // Walk through the directory
-    for entry in WalkDir::new(dir_path).into_iter().filter_map(|e| e.ok()) {
+// Get the metadata and creation date of a file a step is working through. A file that is gone by now was moved
+// or removed by someone else and is passed over, any other problem is recorded and the step goes on with the next file
+pub(crate) fn read_file_date(path: &Path, observer: &dyn Observer, outcome: &mut Outcome) -> Option<(fs::Metadata, NaiveDate)> {
+    match fs::metadata(path).and_then(|metadata| file_created_date(&metadata).map(|date| (metadata, date))) {
+        Ok(found) => Some(found),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            let e = RetryError::from(LogRcError::io(path, e));
+            observer.on_error("reading file", path, &e);
+            outcome.fail(path, "read", e);
+            None
+        }
+    }
+}
+
+pub fn remove_old_files(dir_path: &str, search_str: &str, days: &u64, retry: &RetryPolicy, observer: &dyn Observer, dry_run: bool) -> Result<Outcome, LogRcError> {
+    let mut outcome = Outcome::default();
+    let now = SystemTime::now();
+    let max_age = Duration::from_secs(*days * 24 * 60 * 60);
+    for entry in fs::read_dir(dir_path).with_path(dir_path)? {
+        let entry = entry.with_path(dir_path)?;
         let path = entry.path();
-        if path.is_file() && path.file_name().unwrap().to_str().unwrap().contains(search_string) {
 
-            // Get file creation time
-            let metadata = fs::metadata(path)?;
-            let created: DateTime<Utc> = metadata.created()?.into();
+        if path.is_file() {
+            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+                if file_name.contains(search_str){
+                    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+                        if extension == "log" || extension == "txt" || extension == "zip" {
+                            outcome.scanned.insert(path.clone());
+                            observer.on_file_scanned(&path);
+                            if let Ok(metadata) = fs::metadata(&path) {
+                                if let Ok(modified_time) = metadata.modified() {
+                                    if let Ok(duration) = now.duration_since(modified_time) {
+                                        if duration > max_age {
+                                            if dry_run {
+                                                observer.on_file_deleted(&path, true);
+                                                outcome.deleted.push(FileRecord { path, bytes: metadata.len() });
+                                            } else if let Err(e) = retry.run("remove", &path, observer, || fs::remove_file(&path).with_path(&path)) {
+                                                observer.on_error("removing file", &path, &e);
+                                                outcome.fail(&path, "remove", e);
+                                            } else {
+                                                observer.on_file_deleted(&path, false);
+                                                outcome.deleted.push(FileRecord { path, bytes: metadata.len() });
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+// Count the archives kept in a directory and their total size
+pub fn archive_stats(dir_path: &str, search_str: &str) -> Result<(u64, u64), LogRcError> {
+    let mut count = 0;
+    let mut bytes = 0;
 
-             // Convert to local time with offset
-             let local_time = created.with_timezone(&Local);
-             let offset = local_time.offset().fix();
-             let created_with_offset = created.with_timezone(&offset);
-             let file_date = created_with_offset.date_naive();
+    for entry in fs::read_dir(dir_path).with_path(dir_path)? {
+        let path = entry.with_path(dir_path)?.path();
 
-            // Skip if not a txt or log file
-            if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-                if extension != "log" && extension != "txt" {
-                    continue;
+        if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("zip") {
+            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+                if file_name.contains(search_str) {
+                    count += 1;
+                    bytes += fs::metadata(&path).with_path(&path)?.len();
                 }
             }
+        }
+    }
+
+    Ok((count, bytes))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn group_and_compress_files(dir_path: &str, search_string: &str, active: &ActiveFileCheck, limits: &IoLimits, retry: &RetryPolicy, budget: &RunBudget, observer: &dyn Observer, dry_run: bool) -> Result<Outcome, LogRcError> {
+
+    // Walk through the directory
+    let files: Vec<PathBuf> = WalkDir::new(dir_path).into_iter().filter_map(|e| e.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file() && path.file_name().unwrap().to_str().unwrap().contains(search_string))
+        .collect();
+
+    compress_files(&files, search_string, active, limits, retry, budget, observer, dry_run)
+}
 
-            // Skip files created today
-            if file_date == today {
-                info!("Not compressing file: {:?} because it was made today", path.file_name().unwrap());
+// Group the given files by the day they were created and compress each group into its own zip file
+#[allow(clippy::too_many_arguments)]
+pub fn compress_files(paths: &[PathBuf], search_string: &str, active: &ActiveFileCheck, limits: &IoLimits, retry: &RetryPolicy, budget: &RunBudget, observer: &dyn Observer, dry_run: bool) -> Result<Outcome, LogRcError> {
+    let mut outcome = Outcome::default();
+    let mut file_groups: BTreeMap<String, (PathBuf, Vec<PathBuf>, DateTime<FixedOffset>)> = BTreeMap::new();
+    let today = Local::now().date_naive();
+    let active = active.start();
+
+    for path in paths {
+        // Get file creation time, a file that is gone by now was moved or removed by someone else
+        let (metadata, created) = match fs::metadata(path).and_then(|metadata| metadata.created().map(|created| (metadata, created))) {
+            Ok(found) => found,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                let e = RetryError::from(LogRcError::io(path, e));
+                observer.on_error("reading file", path, &e);
+                outcome.fail(path, "read", e);
                 continue;
             }
+        };
+        let created: DateTime<Utc> = created.into();
 
-            // Use only date for grouping
-            let date_str = file_date.format("%Y-%m-%d").to_string();
+         // Convert to local time with offset
+         let local_time = created.with_timezone(&Local);
+         let offset = local_time.offset().fix();
+         let created_with_offset = created.with_timezone(&offset);
+         let file_date = created_with_offset.date_naive();
 
-            // Group files by date and store the parent directory and oldest creation time
-            let parent_dir = path.parent().unwrap().to_path_buf();
-            file_groups.entry(date_str)
-                .and_modify(|(_, files, oldest_time)| {
-                    files.push(path.to_path_buf());
-                    if created < *oldest_time {
-                        *oldest_time = created_with_offset;
-                    }
-                })
-                .or_insert((parent_dir, vec![path.to_path_buf()], created_with_offset));
+        // Skip if not a txt or log file
+        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+            if extension != "log" && extension != "txt" {
+                continue;
+            }
         }
+        outcome.scanned.insert(path.to_path_buf());
+        observer.on_file_scanned(path);
+
+        // Skip files created today
+        if file_date == today {
+            observer.on_file_skipped(path, "compressing", "it was made today");
+            continue;
+        }
+
+        // Skip files that are still being written, the next run picks them up
+        if let Some(reason) = active.reason(path, &metadata) {
+            observer.on_file_skipped(path, "compressing", &reason);
+            continue;
+        }
+
+        // Use only date for grouping
+        let date_str = file_date.format("%Y-%m-%d").to_string();
+
+        // Group files by date and store the parent directory and oldest creation time
+        let parent_dir = path.parent().unwrap().to_path_buf();
+        file_groups.entry(date_str)
+            .and_modify(|(_, files, oldest_time)| {
+                files.push(path.to_path_buf());
+                if created < *oldest_time {
+                    *oldest_time = created_with_offset;
+                }
+            })
+            .or_insert((parent_dir, vec![path.to_path_buf()], created_with_offset));
     }
 
-    // Create zip files for each group
+    // Create zip files for each group, oldest first so a run that runs out of budget has done the oldest work
     for (date, (parent_dir, files, oldest_time)) in file_groups {
 
-        // Deals with naming the zip file
-        let zip_file_path: PathBuf = get_new_zip_path(&date, parent_dir, &search_string);
+        // Only report the archive that would be made
+        if dry_run {
+            let zip_file_path = budget.pending_archive(&parent_dir, search_string, &date)
+                .unwrap_or_else(|| get_new_zip_path(&date, parent_dir, search_string));
+            observer.on_archive_started(&zip_file_path, files.len(), true);
+            let archived = files.into_iter()
+                .map(|file_path| FileRecord { bytes: fs::metadata(&file_path).map(|metadata| metadata.len()).unwrap_or(0), path: file_path })
+                .collect();
+            outcome.compressed.push(ArchiveRecord { path: zip_file_path, bytes: 0, files: archived });
+            continue;
+        }
+
+        if budget.exhausted() {
+            observer.on_budget_exhausted("Run budget is used up, leaving the remaining groups for the next run");
+            break;
+        }
+
+        // Carry on with the archive the last run left unfinished, rather than starting a -2 archive
+        let pending_path = budget.pending_archive(&parent_dir, search_string, &date);
+        let appending = pending_path.is_some();
+        let zip_file_path: PathBuf = pending_path.unwrap_or_else(|| get_new_zip_path(&date, parent_dir.clone(), search_string));
         
         // Create the zip file with the correct creation time
         let oldest_time = FileTime::from_unix_time(oldest_time.timestamp(), 0);
+
+        // An unfinished archive keeps the time of the oldest file already in it
+        let oldest_time = match appending.then(|| fs::metadata(&zip_file_path)) {
+            Some(Ok(metadata)) => FileTime::from_last_modification_time(&metadata).min(oldest_time),
+            _ => oldest_time,
+        };
         
-        // Create an empty file with the correct creation time
-        let file = OpenOptions::new()
+        // Create an empty file with the correct creation time, or open the unfinished one
+        let file = retry.run("open", &zip_file_path, observer, || OpenOptions::new()
             .write(true)
-            .create_new(true)
-            .open(&zip_file_path)?;
-        
-        // Use a closure to handle zip file creation and return a Result
-        let create_zip = || -> std::io::Result<()> {
-            let mut zip = ZipWriter::new(file);
-
-            let options = SimpleFileOptions::default();
-
-            for file_path in &files {
-                let file_name = file_path.file_name().unwrap().to_str().unwrap();
-                zip.start_file(file_name, options)?;
-                let mut f = File::open(file_path)?;
-                std::io::copy(&mut f, &mut zip)?;
+            .read(true)
+            .create_new(!appending)
+            .open(&zip_file_path)
+            .with_path(&zip_file_path));
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                observer.on_error("creating zip file", &zip_file_path, &e);
+                outcome.fail(&zip_file_path, "open", e);
+                continue;
             }
-
-            zip.finish()?;
-            Ok(())
         };
-
+        observer.on_archive_started(&zip_file_path, files.len(), false);
+        
         // Only remove files if zip creation is successful
-        match create_zip() {
-            Ok(_) => {
-                info!("Created zip file: '{}'", zip_file_path.display());
+        match write_archive(&zip_file_path, file, &files, appending, limits, retry, budget, observer, &mut outcome) {
+            Ok((added, failed)) => {
+                observer.on_archive_finished(&zip_file_path, added.len(), appending);
                 
                 // Set the modification time of the zip file again (creation time should remain unchanged)
-                filetime::set_file_mtime(&zip_file_path, oldest_time)?;
+                filetime::set_file_mtime(&zip_file_path, oldest_time).with_path(&zip_file_path)?;
+
+                // Remember an unfinished group so the next run adds to the same archive
+                if added.len() < files.len() {
+                    if added.len() + failed < files.len() {
+                        observer.on_budget_exhausted(&format!("Run budget is used up, {} file(s) dated {} are left for the next run", files.len() - added.len() - failed, date));
+                    }
+                    budget.set_pending_archive(PendingArchive { directory: parent_dir, filenamecontains: search_string.to_string(), date, path: zip_file_path.clone() });
+                } else {
+                    budget.clear_pending_archive(&parent_dir, search_string, &date);
+                }
                 
                 // Remove original files
-                for file_path in files {
-                    if let Err(e) = fs::remove_file(&file_path) {
-                        error!("Error removing file {}: {}", file_path.display(), e);
+                let mut archived = Vec::new();
+                for file_path in added {
+                    let bytes = fs::metadata(&file_path).map(|metadata| metadata.len()).unwrap_or(0);
+                    if let Err(e) = retry.run("remove", &file_path, observer, || fs::remove_file(&file_path).with_path(&file_path)) {
+                        observer.on_error("removing file", &file_path, &e);
+                        outcome.fail(&file_path, "remove", e);
                     } else {
-                        info!("Removed file: '{}'", file_path.display());
+                        observer.on_file_deleted(&file_path, false);
                     }
+                    archived.push(FileRecord { path: file_path, bytes });
                 }
+
+                let bytes = fs::metadata(&zip_file_path).with_path(&zip_file_path)?.len();
+                outcome.compressed.push(ArchiveRecord { path: zip_file_path, bytes, files: archived });
             },
             Err(e) => {
-                error!("Error creating zip file {}: {}", zip_file_path.display(), e);
-                // Try to remove the partially created zip file
-                if let Err(remove_err) = fs::remove_file(&zip_file_path) {
-                    error!("Error removing partial zip file: {}", remove_err);
+                outcome.errors.push(format!("Error creating zip file: {}", e));
+                observer.on_error("creating zip file", &zip_file_path, &e.into());
+                // Try to remove the partially created zip file. An unfinished archive from the last run holds files that are already gone
+                if !appending {
+                    if let Err(remove_err) = fs::remove_file(&zip_file_path) {
+                        observer.on_error("removing partial zip file", &zip_file_path, &LogRcError::io(&zip_file_path, remove_err).into());
+                    }
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(outcome)
+}
+
+// Write a date group's files into a new archive, or add them to the unfinished one an earlier run left.
+// Returns the files that are in the archive now and how many could not be opened
+#[allow(clippy::too_many_arguments)]
+fn write_archive(zip_file_path: &Path, file: File, files: &[PathBuf], appending: bool, limits: &IoLimits, retry: &RetryPolicy, budget: &RunBudget, observer: &dyn Observer, outcome: &mut Outcome) -> Result<(Vec<PathBuf>, usize), LogRcError> {
+    let mut added = Vec::new();
+    let mut failed = 0;
+    let bytes_total = files.iter().map(|file_path| fs::metadata(file_path).map(|metadata| metadata.len()).unwrap_or(0)).sum();
+    let mut bytes_done = 0;
+
+    // The size and CRC-32 of each file an unfinished archive already holds
+    let mut pending: HashMap<String, (u64, u32)> = HashMap::new();
+    if appending {
+        let mut archive = ZipArchive::new(&file).map_err(|e| LogRcError::archive(zip_file_path, e))?;
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index).map_err(|e| LogRcError::archive(zip_file_path, e))?;
+            pending.insert(entry.name().to_string(), (entry.size(), entry.crc32()));
+        }
+    }
+    // Files in one group can share a name when they come from different sub directories
+    let mut written: HashSet<String> = HashSet::new();
+
+    let writer = ThrottledWriter::new(file, limits.maxwriterate);
+    let mut zip = if appending {
+        ZipWriter::new_append(writer).map_err(|e| LogRcError::archive(zip_file_path, e))?
+    } else {
+        ZipWriter::new(writer)
+    };
+
+    let options = SimpleFileOptions::default();
+
+    for file_path in files {
+        let file_name = file_path.file_name().unwrap().to_str().unwrap();
+
+        // A file the last run put in the archive but could not remove only needs removing now
+        if let Some((size, crc32)) = pending.get(file_name) {
+            let unchanged = fs::metadata(file_path).is_ok_and(|metadata| metadata.len() == *size)
+                && file_crc32(file_path).is_ok_and(|file_crc32| file_crc32 == *crc32);
+            if unchanged {
+                added.push(file_path.clone());
+                continue;
+            }
+        }
+
+        if !budget.take_file() {
+            break;
+        }
+        // A file that stays locked is left out and goes into this archive on the next run
+        let source = match retry.run("open", file_path, observer, || File::open(file_path).with_path(file_path)) {
+            Ok(source) => source,
+            Err(e) => {
+                observer.on_error("opening file", file_path, &e);
+                outcome.fail(file_path, "open", e);
+                failed += 1;
+                continue;
+            }
+        };
+        // A different file with the same name as one already in the archive goes in under a new name
+        let entry_name = unique_entry_name(file_name, |name| pending.contains_key(name) || written.contains(name));
+        zip.start_file(entry_name.as_str(), options).map_err(|e| LogRcError::archive(zip_file_path, e))?;
+        let mut f = ThrottledReader::new(source, limits.maxreadrate);
+        bytes_done += std::io::copy(&mut f, &mut zip).with_path(file_path)?;
+        written.insert(entry_name);
+        added.push(file_path.clone());
+        observer.on_archive_progress(zip_file_path, file_path, bytes_done, bytes_total);
+    }
+
+    zip.finish().map_err(|e| LogRcError::archive(zip_file_path, e))?;
+    Ok((added, failed))
+}
+
+// The CRC-32 of a file's contents, which zip keeps for every entry
+fn file_crc32(path: &Path) -> std::io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+// The file name, or for a name the archive already holds the name with -2, -3 and so on before the extension
+fn unique_entry_name(file_name: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(file_name) {
+        return file_name.to_string();
+    }
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (file_name, String::new()),
+    };
+    (2..).map(|copy| format!("{}-{}{}", stem, copy, extension))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+// The last archive made for the date, if there is one
+pub fn latest_zip_path(date: &str, basepath: &Path, search_string: &str) -> Option<PathBuf> {
+    (1..).map(|zip_int| basepath.join(format!("{}_{}-{}.zip", date, search_string, zip_int)))
+        .take_while(|zip_file_path| zip_file_path.exists())
+        .last()
 }
 
 pub fn get_new_zip_path (date: &str, basepath: PathBuf, search_string: &str) -> PathBuf{
@@ -136,35 +434,35 @@ pub fn get_new_zip_path (date: &str, basepath: PathBuf, search_string: &str) ->
         }
     }
 
-    return zip_file_path
+    zip_file_path
 
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn move_files_except_today(
     source_dir: &str,
     dest_dir: &str,
-    filename_contains: &str
-) -> std::io::Result<()> {
+    filename_contains: &str,
+    active: &ActiveFileCheck,
+    limits: &IoLimits,
+    retry: &RetryPolicy,
+    budget: &RunBudget,
+    observer: &dyn Observer,
+    dry_run: bool
+) -> Result<Outcome, LogRcError> {
+    let mut outcome = Outcome::default();
     let today = Local::now().date_naive();
+    let active = active.start();
 
-    create_status_file(&source_dir,&filename_contains,&dest_dir,today)?;
-
-    for entry in fs::read_dir(source_dir)? {
-        let entry = entry?;
+    for entry in fs::read_dir(source_dir).with_path(source_dir)? {
+        let entry = entry.with_path(source_dir)?;
         let path = entry.path();
 
         if path.is_file() {
             let filename = path.file_name().unwrap().to_string_lossy();
             
             // Get the file's creation date
-            let metadata = fs::metadata(&path)?;
-            let created: DateTime<Utc> = metadata.created()?.into();
-
-            // Convert to local time with offset
-            let local_time = created.with_timezone(&Local);
-            let offset = local_time.offset().fix();
-            let created_with_offset = created.with_timezone(&offset);
-            let file_date = created_with_offset.date_naive();
+            let Some((metadata, file_date)) = read_file_date(&path, observer, &mut outcome) else { continue };
 
             if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
 
@@ -173,15 +471,40 @@ pub fn move_files_except_today(
 
                     // Check if the file was created today
                     if file_date == today {
-                        info!("Not moving file: {:?} because it was made today", path.file_name().unwrap());
+                        observer.on_file_skipped(&path, "moving", "it was made today");
                         continue;
                     }
 
                     // Check if the filename contains the specified string
                     if filename.contains(filename_contains) {
+                        outcome.scanned.insert(path.clone());
+                        observer.on_file_scanned(&path);
+                        if let Some(reason) = active.reason(&path, &metadata) {
+                            observer.on_file_skipped(&path, "moving", &reason);
+                            continue;
+                        }
                         let new_path = Path::new(dest_dir).join(path.file_name().unwrap());
-                        fs::rename(&path, &new_path)?;
-                        info!("Moved file: '{}' to '{}'", path.display(), new_path.display());
+                        if !dry_run && !budget.take_file() {
+                            observer.on_budget_exhausted("Run budget is used up, leaving the remaining files to move for the next run");
+                            break;
+                        }
+                        if dry_run {
+                            observer.on_file_moved(&path, &new_path.display().to_string(), true);
+                        } else {
+                            // A rename can not cross filesystems, so copy the file over instead
+                            let moved = retry.run("move", &path, observer, || match fs::rename(&path, &new_path) {
+                                Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => throttle::copy_and_remove(&path, &new_path, limits),
+                                result => result.with_path(&path),
+                            });
+                            // Carry on with the other files, this one stays where it is for the next run
+                            if let Err(e) = moved {
+                                observer.on_error("moving file", &path, &e);
+                                outcome.fail(&path, "move", e);
+                                continue;
+                            }
+                            observer.on_file_moved(&path, &new_path.display().to_string(), false);
+                        }
+                        outcome.moved.push(MoveRecord { from: path.clone(), to: new_path.display().to_string(), bytes: metadata.len() });
 
                     }
                 }
@@ -189,88 +512,112 @@ pub fn move_files_except_today(
         }
     }
 
-    Ok(())
+    Ok(outcome)
 }
 
-pub fn create_status_file (source_dir: &str, filename_contains: &str, dest_dir: &str, today: NaiveDate) -> std::io::Result<()>{
-
-    // Declare some const for naming and filling file with content
-    const FILE_SUFFIX: &str = "files have been moved.status";
-    const MESSAGE_TEMPLATE1: &str = "files older than";
-    const MESSAGE_TEMPLATE2: &str = "were moved to";
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
 
-    // Create the path and name, join them together
-    let file_name = format!("{} {}", filename_contains, FILE_SUFFIX);
-    let content = format!("'{}\\*{}*.[log|txt|zip]' {} {} {} '{}'", source_dir, filename_contains, MESSAGE_TEMPLATE1, today, MESSAGE_TEMPLATE2, dest_dir);
-    let file_path: PathBuf = Path::new(source_dir).join(file_name);
-
-    // Attempt to remove the file if it exists
-    match remove_file(&file_path) {
-        Ok(_) => (),
-        Err(e) if e.kind() == ErrorKind::NotFound => (), // File doesn't exist, which is fine
-        Err(e) => return Err(e), // Other errors should be propagated
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("logrc-lib-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
     }
 
-    // Make the status file with content
-    let mut file = File::create(&file_path)?;
-    file.write_all(content.as_bytes())?;
-    info!("Created a status file at '{}'", file_path.display());
-
-    Ok(())
-
-}
+    fn write(path: &Path, contents: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        path.to_path_buf()
+    }
 
-pub fn config_application_setting_checker (dir_retentionindays: &u64) -> bool {
+    // Every entry in an archive with its contents
+    fn entries(zip_file_path: &Path) -> Vec<(String, String)> {
+        let mut archive = ZipArchive::new(File::open(zip_file_path).unwrap()).unwrap();
+        (0..archive.len()).map(|index| {
+            let mut entry = archive.by_index(index).unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            (entry.name().to_string(), contents)
+        }).collect()
+    }
 
-        // Retentionindays should not be 0
-        if *dir_retentionindays < 1 {
-            error!("[application]logretentionindays setting should be a number between 2-365. Application will now close.");
-            return false
-        }
-    
-        // Retentionindays should not be greater then 365
-        if *dir_retentionindays > 365 {
-            error!("[application]logretentionindays setting should be a number between 2-365. Application will now close.");
-            return false
-        }
+    fn write_group(zip_file_path: &Path, files: &[PathBuf], appending: bool, budget: &RunBudget) -> (Vec<PathBuf>, usize) {
+        let file = OpenOptions::new().write(true).read(true).create(true).truncate(false).open(zip_file_path).unwrap();
+        write_archive(zip_file_path, file, files, appending, &IoLimits::default(), &RetryPolicy::default(), budget, &LogObserver, &mut Outcome::default()).unwrap()
+    }
 
-        // No issues with the config file. No ID 10T errors here!
-        true
-}
+    #[test]
+    fn same_names_from_sub_directories_are_all_archived() {
+        let dir = test_dir("subdirs");
+        let first = write(&dir.join("a/app.log"), "first");
+        let second = write(&dir.join("b/app.log"), "other");
+        let zip_file_path = dir.join("2026-01-01_app-1.zip");
 
-pub fn config_directory_setting_checker (dir_path: &str, dir_filenamecontains: &str, dir_retentionindays: &u64) -> bool {
+        let (added, failed) = write_group(&zip_file_path, &[first.clone(), second.clone()], false, &RunBudget::default());
 
-    // path should be a directory
-    let path = Path::new(&dir_path);
-    if !path.is_dir() {
-        warn!("[directory]path setting should be an existing directory but is set to '{}'.", dir_path);
-        return false
+        assert_eq!(added, [first, second]);
+        assert_eq!(failed, 0);
+        assert_eq!(entries(&zip_file_path), [("app.log".to_string(), "first".to_string()), ("app-2.log".to_string(), "other".to_string())]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    // filenamecontains should not be blank
-    if dir_filenamecontains == "" {
-        warn!("[directory]filenamecontains setting should not be blank for the path '{}'", dir_path);
-        return false
-    }
+    #[test]
+    fn resumed_archive_only_skips_files_it_already_holds() {
+        let dir = test_dir("resume");
+        let zip_file_path = dir.join("2026-01-01_app-1.zip");
+        {
+            let mut zip = ZipWriter::new(File::create(&zip_file_path).unwrap());
+            for (name, contents) in [("app.log", "kept"), ("app.1.log", "older")] {
+                zip.start_file(name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(contents.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+        }
 
-    // filenamecontains should not contain a '_'
-    if dir_filenamecontains.contains('_') {
-        warn!("[directory]filenamecontains setting should not contain a '_' for the path '{}'", dir_path);
-        return false
+        // The last run archived app.log but could not remove it, app.1.log is a new file with the same name and size
+        let kept = write(&dir.join("app.log"), "kept");
+        let renamed = write(&dir.join("app.1.log"), "newer");
+        let fresh = write(&dir.join("app.2.log"), "fresh");
+        let (added, _) = write_group(&zip_file_path, &[kept.clone(), renamed.clone(), fresh.clone()], true, &RunBudget::default());
+
+        assert_eq!(added, [kept, renamed, fresh]);
+        assert_eq!(entries(&zip_file_path), [
+            ("app.log".to_string(), "kept".to_string()),
+            ("app.1.log".to_string(), "older".to_string()),
+            ("app.1-2.log".to_string(), "newer".to_string()),
+            ("app.2.log".to_string(), "fresh".to_string()),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    // Retentionindays should not be 0
-    if *dir_retentionindays == 0 {
-        warn!("[directory]retentionindays setting should be a number between 1-365 for Path '{}', Name '{}'", dir_path, dir_filenamecontains);
-        return false
+    #[test]
+    fn archive_stops_when_the_file_budget_runs_out() {
+        let dir = test_dir("budget");
+        let files: Vec<PathBuf> = (1..=3).map(|index| write(&dir.join(format!("app.{}.log", index)), "line")).collect();
+        let zip_file_path = dir.join("2026-01-01_app-1.zip");
+
+        let (added, _) = write_group(&zip_file_path, &files, false, &RunBudget::new(None, Some(2), Vec::new()));
+        assert_eq!(added, files[..2]);
+
+        // The next run carries on with the same archive
+        let (added, _) = write_group(&zip_file_path, &files, true, &RunBudget::default());
+        assert_eq!(added, files);
+        let names: Vec<String> = entries(&zip_file_path).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["app.1.log", "app.2.log", "app.3.log"]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    // Retentionindays should not be greater then 365
-    if *dir_retentionindays > 365 {
-        warn!("[directory]retentionindays setting should be a number between 1-365 for Path '{}', Name '{}'", dir_path, dir_filenamecontains);
-        return false
+    #[test]
+    fn latest_zip_path_finds_the_last_numbered_archive() {
+        let dir = test_dir("latest");
+        assert_eq!(latest_zip_path("2026-01-01", &dir, "app"), None);
+        write(&dir.join("2026-01-01_app-1.zip"), "");
+        write(&dir.join("2026-01-01_app-2.zip"), "");
+        assert_eq!(latest_zip_path("2026-01-01", &dir, "app"), Some(dir.join("2026-01-01_app-2.zip")));
+        assert_eq!(get_new_zip_path("2026-01-01", dir.clone(), "app"), dir.join("2026-01-01_app-3.zip"));
+        fs::remove_dir_all(&dir).unwrap();
     }
-
-    // No issues with the config file. No ID 10T errors here!
-    true
-}
\ No newline at end of file
+}
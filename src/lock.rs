@@ -0,0 +1,188 @@
+use clap::ValueEnum;
+use log_rc::LogRcError;
+use log::{info, warn};
+use std::{fs::{File, OpenOptions, TryLockError}, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
+
+// How often a waiting run checks the lock again
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+// What to do when another run already holds the lock
+#[derive(ValueEnum, Clone, Copy, Default)]
+pub enum LockMode {
+    /// Wait for the other run to finish
+    Wait,
+    /// Exit without doing anything
+    Skip,
+    /// Exit with an error
+    #[default]
+    Fail,
+}
+
+// Held for as long as the run goes on. The lock is released when the file is closed, even if the process dies
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+    // The PID an earlier run left in the file and whether it is still running
+    left_by: Option<(u32, bool)>,
+}
+
+impl RunLock {
+    // The lock is taken before the log is opened, so what happened is logged once it is
+    pub fn log_taken(&self) {
+        match self.left_by {
+            Some((pid, true)) => warn!("Taking over lock file '{}' from PID {} which no longer holds it", self.path.display(), pid),
+            Some((pid, false)) => warn!("Removing stale lock left in '{}' by PID {} which is no longer running", self.path.display(), pid),
+            None => (),
+        }
+        info!("Took the lock '{}'", self.path.display());
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Clear the PID but leave the file, removing it would let a waiting run lock a file nobody else can see
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+// The default lock file sits next to the config file
+pub fn default_lock_path(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new("")).join("LogRC.lock")
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn process_running(pid: u32) -> bool {
+    // Signal 0 only checks the process exists, EPERM means it exists but belongs to someone else
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+fn try_acquire(path: &Path) -> Result<RunLock, LogRcError> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| LogRcError::io(parent, e))?;
+    }
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).map_err(|e| LogRcError::io(path, e))?;
+
+    // flock on Linux, LockFileEx on Windows
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => return Err(LogRcError::Lock { path: path.to_path_buf(), pid: read_pid(&mut file) }),
+        Err(TryLockError::Error(e)) => return Err(LogRcError::io(path, e)),
+    }
+
+    // A PID left behind means the last run did not shut down cleanly
+    let left_by = read_pid(&mut file).map(|pid| (pid, process_running(pid)));
+
+    file.set_len(0)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| write!(file, "{}", std::process::id()))
+        .and_then(|_| file.flush())
+        .map_err(|e| LogRcError::io(path, e))?;
+
+    Ok(RunLock { file, path: path.to_path_buf(), left_by })
+}
+
+fn held_by(pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) if process_running(pid) => format!("PID {}", pid),
+        Some(pid) => format!("PID {} which is no longer running, another process may have inherited the lock", pid),
+        None => "another process".to_string(),
+    }
+}
+
+// Take the single instance lock. Returns Ok(None) when the run should be skipped.
+// This runs before the log is opened, so a run that skips or waits says so on stderr
+pub fn acquire(path: &Path, mode: LockMode, timeout: Option<Duration>) -> Result<Option<RunLock>, LogRcError> {
+    let started = Instant::now();
+    let mut waiting = false;
+
+    loop {
+        match try_acquire(path) {
+            Ok(lock) => return Ok(Some(lock)),
+            Err(LogRcError::Lock { pid, .. }) => match mode {
+                LockMode::Skip => {
+                    eprintln!("Skipping this run because lock '{}' is held by {}", path.display(), held_by(pid));
+                    return Ok(None);
+                },
+                LockMode::Fail => return Err(LogRcError::Lock { path: path.to_path_buf(), pid }),
+                LockMode::Wait => {
+                    if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                        return Err(LogRcError::Lock { path: path.to_path_buf(), pid });
+                    }
+                    if !waiting {
+                        eprintln!("Waiting for lock '{}' held by {}", path.display(), held_by(pid));
+                        waiting = true;
+                    }
+                    thread::sleep(WAIT_INTERVAL);
+                },
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Says whether the PID in a held lock is still running
+pub fn describe(error: &LogRcError) -> String {
+    match error {
+        LogRcError::Lock { path, pid } => format!("Lock '{}' is held by {}", path.display(), held_by(*pid)),
+        e => format!("Failed to take the lock {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("logrc-lock-test-{}-{}", name, std::process::id())).join("LogRC.lock");
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn second_run_skips_or_fails_while_the_lock_is_held() {
+        let path = lock_path("held");
+        let lock = acquire(&path, LockMode::Fail, None).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+
+        assert!(acquire(&path, LockMode::Skip, None).unwrap().is_none());
+        match acquire(&path, LockMode::Fail, None) {
+            Err(LogRcError::Lock { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            _ => panic!("expected the lock to be held"),
+        }
+        assert!(acquire(&path, LockMode::Wait, Some(Duration::ZERO)).is_err());
+
+        // Releasing clears the PID and lets the next run in
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(acquire(&path, LockMode::Fail, None).unwrap().is_some());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn pid_left_by_a_run_that_died_is_taken_over() {
+        let path = lock_path("stale");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "4000000").unwrap();
+
+        let lock = acquire(&path, LockMode::Fail, None).unwrap().unwrap();
+        assert_eq!(lock.left_by.map(|(pid, _)| pid), Some(4000000));
+        assert_eq!(fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+        drop(lock);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -2,50 +2,88 @@ extern crate simplelog;
 extern crate chrono;
 
 use simplelog::*;
-use log::{info, error};
-use std::{fs::{self, File}, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+use log::{info, warn, error};
+use std::{fmt, fs::File, io::{self, Write}, path::{Path, PathBuf}, process::ExitCode, time::Instant};
 use chrono::*;
 use time::UtcOffset;
-use serde::Deserialize;
-use toml;
-use std::process;
+use serde::Serialize;
 use log_rc::*;
+use clap::Parser;
+
+mod cli;
+mod daemon;
+mod lock;
+mod reload;
+mod state;
+mod watch;
+use cli::{Cli, Command, LockArgs, PlanFormat, RuleSelection, RunArgs};
+use lock::RunLock;
 
 const APP_NAME: &str = "LogRC";
 const LOG_NAME: &str = "LogRetentionandCompression";
 
-#[derive(Deserialize)]
-struct Directory {
-    path: String,
-    filenamecontains: String,
-    retentionindays: u64,
-    compress: bool,
-    movetopath: String
+// Exit codes returned to the scheduler that started the run
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ExitStatus {
+    Success = 0,
+    ConfigError = 2,
+    PartialFailure = 3,
+    TotalFailure = 4,
+    LockHeld = 5,
 }
 
-#[derive(Deserialize)]
-struct Directories {
-    directory: Vec<Directory>,
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> ExitCode {
+        ExitCode::from(status as u8)
+    }
 }
 
-#[derive(Deserialize)]
-struct Application {
-    logretentionindays: u64,
+// Errors that stop a run before or while it starts
+#[derive(Debug)]
+enum AppError {
+    Config(Vec<ConfigError>),
+    Logger(String),
+    Locked(String),
 }
 
-#[derive(Deserialize)]
-struct ConfigFile {
-    directories: Directories,
-    application: Application,
+impl AppError {
+    fn exit_status(&self) -> ExitStatus {
+        match self {
+            AppError::Config(_) => ExitStatus::ConfigError,
+            AppError::Logger(_) => ExitStatus::TotalFailure,
+            AppError::Locked(_) => ExitStatus::LockHeld,
+        }
+    }
 }
 
-fn load_config(path: &str) -> Result<ConfigFile, Box<dyn std::error::Error>> {
-    let contents = fs::read_to_string(path)?;
-    let config_file: ConfigFile = toml::from_str(&contents)?;
-    Ok(config_file)
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Config(errors) => {
+                write!(f, "Config error:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            },
+            AppError::Logger(message) => write!(f, "Failed to initialize logger: {}", message),
+            AppError::Locked(message) => write!(f, "{}", message),
+        }
+    }
 }
 
-fn starttask(days: &u64) -> Instant{
+// The application log directory sits next to the config file so the working directory does not matter
+fn log_directory(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new("")).join("log")
+}
+
+// Make sure only one run works on the directories at a time. Returns None when this run should be skipped
+fn take_lock(config_path: &Path, application: &Application, args: &LockArgs) -> Result<Option<RunLock>, AppError> {
+    let lock_path = application.lockfile.as_ref().map(PathBuf::from).unwrap_or_else(|| lock::default_lock_path(config_path));
+    lock::acquire(&lock_path, args.mode, args.timeout()).map_err(|e| AppError::Locked(lock::describe(&e)))
+}
+
+fn starttask(application: &Application, log_dir: &Path, config_errors: &[ConfigError]) -> Result<Instant, AppError> {
 
     // Capture the start time
     let start_time = Instant::now();
@@ -56,40 +94,64 @@ fn starttask(days: &u64) -> Instant{
     //info!("This only appears in the log file");
 
     // Initialize the logger
-    init_logger(LOG_NAME).expect("Failed to initialize logger");
+    init_logger(log_dir, LOG_NAME).map_err(|e| AppError::Logger(e.to_string()))?;
     
     // Start up text
     let version = env!("CARGO_PKG_VERSION");
     info!("Starting {} v{}", APP_NAME, version);
 
-    // Verify Application config settings
-    if config_application_setting_checker(&days) {
-        
-        // Remove old Application log files
-        info!("Application log retention: {} days", days);
-        remove_old_files("log", LOG_NAME, days).expect("Failed to remove application logs past retention");
-        
-        } else {
-        // Should work on making the exit call get back to main
-        process::exit(1);
+    // Report every config problem, the run can not go ahead if the application settings are wrong
+    for config_error in config_errors {
+        error!("{}", config_error);
+    }
+    let application_errors: Vec<ConfigError> = config_errors.iter().filter(|config_error| config_error.rule.is_none()).cloned().collect();
+    if !application_errors.is_empty() {
+        return Err(AppError::Config(application_errors));
+    }
+
+    // Lower the priority before any housekeeping starts, threads started later inherit it
+    apply_priority(application);
 
+    // Remove old Application log files
+    let days = &application.logretentionindays;
+    info!("Application log retention: {} days", days);
+    if let Err(e) = remove_old_files(&log_dir.to_string_lossy(), LOG_NAME, days, &RetryPolicy::default(), &LogObserver, false) {
+        error!("Failed to remove application logs past retention: {}", e);
     }
 
-    start_time
+    Ok(start_time)
 
 }
 
-fn endtasks(start_time: Instant){
+fn endtasks(start_time: Instant, started: DateTime<Local>, directories: Vec<DirectorySummary>, application: &Application){
 
     // Calculate the elapsed time
     let as_sec: u64 = start_time.elapsed().as_secs();
-    
+
+    // Print the run summary
+    let summary = RunSummary::new(started.to_rfc3339(), Local::now().to_rfc3339(), as_sec, directories);
+    summary.log();
+
+    // Write the summary as JSON if it is set
+    if let Some(summary_file) = &application.summaryfile {
+        if let Err(e) = write_summary_file(summary_file, &summary) {
+            error!("There was an issue creating the summary file: {}", e);
+        }
+    }
+
+    // Write the Prometheus metrics if it is set
+    if let Some(metrics_file) = &application.metricsfile {
+        if let Err(e) = write_metrics_file(metrics_file, &summary) {
+            error!("There was an issue creating the metrics file: {}", e);
+        }
+    }
+
     // Print the elapsed time
     info!("Application ran for: {} second(s)", as_sec);
     
 }
 
-fn setuplogfilename (log_name: &str) -> PathBuf{
+fn setuplogfilename (log_dir: &Path, log_name: &str) -> std::io::Result<PathBuf>{
 
     // Get the current date
     let current_date = Local::now().date_naive();
@@ -98,14 +160,13 @@ fn setuplogfilename (log_name: &str) -> PathBuf{
     let date_string = current_date.format("%Y-%m-%d").to_string();
 
     // Create the directory if it doesn't exist
-    std::fs::create_dir_all("log").expect("Failed to create log directory");
+    std::fs::create_dir_all(log_dir)?;
 
     // Get a log name that does not exist
-    let log_directory: PathBuf = Path::new("log").to_path_buf();
-    let log_file_new_path: PathBuf = get_new_log_path(&date_string, log_directory, &log_name);
+    let log_file_new_path: PathBuf = get_new_log_path(&date_string, log_dir.to_path_buf(), log_name);
 
     // returns the path
-    log_file_new_path
+    Ok(log_file_new_path)
 }
 
 fn get_new_log_path (date: &str, basepath: PathBuf, log_name: &str) -> PathBuf{
@@ -127,20 +188,47 @@ fn get_new_log_path (date: &str, basepath: PathBuf, log_name: &str) -> PathBuf{
         }
     }
 
-    return log_file_path
+    log_file_path
+
+}
 
+// Log file that moves on to a new dated file when the day changes, so a long running daemon keeps one log per day
+struct DailyLogFile {
+    log_dir: PathBuf,
+    log_name: String,
+    date: NaiveDate,
+    file: File,
+}
+
+impl DailyLogFile {
+    fn open(log_dir: &Path, log_name: &str) -> io::Result<DailyLogFile> {
+        let file = File::create(setuplogfilename(log_dir, log_name)?)?;
+        Ok(DailyLogFile { log_dir: log_dir.to_path_buf(), log_name: log_name.to_string(), date: Local::now().date_naive(), file })
+    }
 }
 
-fn init_logger(log_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+impl Write for DailyLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = Local::now().date_naive();
+        if today != self.date {
+            self.file.flush()?;
+            *self = DailyLogFile::open(&self.log_dir, &self.log_name)?;
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
 
-    // Get the log file path
-    let log_file_path = setuplogfilename(&log_name);
+fn init_logger(log_dir: &Path, log_name: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     // Open the log file
-    let log_file = File::create(log_file_path)?;
+    let log_file = DailyLogFile::open(log_dir, log_name)?;
 
     // Get the UTC offset for the log datetime
-    let local_offset = UtcOffset::current_local_offset().expect("Failed to get local UTC offset");
+    let local_offset = UtcOffset::current_local_offset()?;
 
     // Custom log format to include the function name
     let config = ConfigBuilder::new()
@@ -149,127 +237,347 @@ fn init_logger(log_name: &str) -> Result<(), Box<dyn std::error::Error>> {
         .set_location_level(LevelFilter::Debug)  // Remove this line
         .build();
 
-    // Initialize the logger
-    CombinedLogger::init(
+    // Initialize the logger, wrapped so the lines from worker threads are labelled with their rule
+    let logger = CombinedLogger::new(
         vec![
             TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
             WriteLogger::new(LevelFilter::Debug, config, log_file),
         ]
-    ).unwrap();
+    );
+    log::set_boxed_logger(Box::new(RuleLogger::new(logger)))?;
+    log::set_max_level(LevelFilter::Debug);
     
     Ok(())
 }
 
-fn remove_old_files(dir_path: &str, search_str: &str, days: &u64) -> std::io::Result<()> {
-    let now = SystemTime::now();
-    let max_age = Duration::from_secs((*days * 24 * 60 * 60).into());
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() {
-            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
-                if file_name.contains(search_str){
-                    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-                        if extension == "log" || extension == "txt" || extension == "zip" {
-                            if let Ok(metadata) = fs::metadata(&path) {
-                                if let Ok(modified_time) = metadata.modified() {
-                                    if let Ok(duration) = now.duration_since(modified_time) {
-                                        if duration > max_age {
-                                            if let Err(e) = fs::remove_file(&path) {
-                                                error!("Error removing file {}: {}", path.display(), e);
-                                            } else {
-                                                info!("Removed file: '{}'", path.display());
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+fn has_rule_errors(config_errors: &[ConfigError], index: usize) -> bool {
+    config_errors.iter().any(|config_error| config_error.rule == Some(index + 1))
+}
+
+fn no_matching_rules(selection: &RuleSelection) -> ConfigError {
+    ConfigError::new(None, "--only", ConfigErrorKind::NoMatchingRules, format!("no directory rules match {}", selection.only.join(", ")))
+}
+
+fn run(config_file: &ConfigFile, config_path: &Path, selection: &RuleSelection, lock_args: &LockArgs) -> Result<ExitStatus, AppError> {
+
+    // Starting Tasks
+    let run_started = Local::now();
+    let config_errors = validate_config(config_file);
+
+    // Take the lock before opening the log, so a skipped run does not start a log file or clean up the log directory
+    let lock = match take_lock(config_path, &config_file.application, lock_args)? {
+        Some(lock) => lock,
+        None => return Ok(ExitStatus::Success),
+    };
+    let start_time = starttask(&config_file.application, &log_directory(config_path), &config_errors)?;
+    lock.log_taken();
+    if config_file.version < CURRENT_VERSION {
+        warn!("Config file uses schema version {}, run '{} migrate-config' to upgrade it to version {}", config_file.version, APP_NAME, CURRENT_VERSION);
+    }
+    let mut summaries: Vec<DirectorySummary> = Vec::new();
+    let mut failed_rules = 0;
+
+    // Only keep the directory rules picked with --only
+    let selected: Vec<(usize, &Directory)> = config_file.directories.directory.iter().enumerate()
+        .filter(|(index, dir)| selection.selects(*index, dir.name.as_deref(), &dir.path, &dir.filenamecontains))
+        .collect();
+    if selected.is_empty() {
+        return Err(AppError::Config(vec![no_matching_rules(selection)]));
+    }
+
+    // For Each each directory imported from config file
+    let mut runnable = Vec::new();
+    for (index, dir) in &selected {
+
+        // Skip the rule if the config settings have problems
+        if has_rule_errors(&config_errors, *index) {
+            error!("Skipping {} because of config errors", dir.label());
+            failed_rules += 1;
+            continue;
         }
+        info!("Directory Config settings are correct for {}", dir.label());
+        runnable.push((*dir).clone());
+
     }
 
-    Ok(())
+    // Carry on from where the last run ran out of budget
+    let state_path = state::state_path(config_path, &config_file.application);
+    let run_state = state::load(&state_path);
+    // A rule left out by --only stays the place to resume from unless this run stops part way itself
+    let (resume_from, elsewhere) = state::resume_point(run_state.nextrule, runnable.iter());
+    let mut builder = Policy::builder()
+        .application(&config_file.application)
+        .rules(runnable)
+        .pending_archives(run_state.pendingarchives);
+    if let Some(resume_from) = resume_from {
+        builder = builder.resume_from(resume_from);
+    }
+    let policy = builder.build().map_err(AppError::Config)?;
+
+    let report = Engine::new().run(&policy);
+    state::save(&state_path, &state::RunState { nextrule: report.next_rule.clone().or(elsewhere), pendingarchives: report.pending_archives.clone() });
+    failed_rules += report.failed_rules();
+    summaries.extend(report.rules.into_iter().map(|rule_report| rule_report.summary));
+
+    // Stopping Tasks
+    let total_rules = selected.len();
+    endtasks(start_time, run_started, summaries, &config_file.application);
+
+    if failed_rules == 0 {
+        Ok(ExitStatus::Success)
+    } else if failed_rules < total_rules {
+        error!("{} of {} directory rules failed", failed_rules, total_rules);
+        Ok(ExitStatus::PartialFailure)
+    } else {
+        error!("All {} directory rules failed", total_rules);
+        Ok(ExitStatus::TotalFailure)
+    }
+
+}
+
+// Where files end up after compression, if anywhere
+fn destination_label(dir: &Directory) -> String {
+    if let Some(sftp) = &dir.sftp {
+        format!("sftp://{}{}", sftp.host, sftp.remotepath)
+    } else if dir.movetopath.is_empty() {
+        "none".to_string()
+    } else {
+        dir.movetopath.clone()
+    }
 }
 
-fn main() {
+fn validate(config_file: &ConfigFile) -> ExitStatus {
 
-    // Load config file
-    let config_file_name = format!("{}.toml", APP_NAME);
-    match load_config(&config_file_name) {
-        Ok(config_file) => {
-
-            // Starting Tasks
-            let start_time = starttask(&config_file.application.logretentionindays);
-
-            // For Each each directory imported from config file
-            for dir in &config_file.directories.directory {
-                //debug!("Path: {}, FileName {}, Retention: {}, Compress {}, MoveTo {}", dir.path, dir.filenamecontains, dir.retentionindays, dir.compress, dir.movetopath);
-                
-                // Verify the config settings
-                if config_directory_setting_checker(&dir.path, &dir.filenamecontains, &dir.retentionindays) {
-                    info!("Directory Config settings are correct for Path '{}', Name '{}'", dir.path, dir.filenamecontains);
-                }else{
-                    continue;
-                }
+    let config_errors = validate_config(config_file);
+    if config_errors.is_empty() {
+        println!("Config is valid, {} directory rule(s)", config_file.directories.directory.len());
+        return ExitStatus::Success;
+    }
 
-                // Remove old log files
-                info!("Removing files with a date modified older then {} days for FilePath '{}\\*{}*.[log|txt|zip]'", dir.retentionindays, dir.path, dir.filenamecontains);
-                match remove_old_files(&dir.path, &dir.filenamecontains, &dir.retentionindays) {
-                    Ok(_) => info!("Completed file retention"),
-                    Err(e) => error!("There was an issue removing the files: {}", e),
-                
-                }
+    for config_error in &config_errors {
+        println!("{:?}: {}", config_error.kind, config_error);
+    }
+    println!("Found {} problem(s) in the config", config_errors.len());
+    ExitStatus::ConfigError
+}
 
-                // Daily Compress log files
-                if dir.compress == true {
-                    info!("Compressing files older then today for FilePath '{}\\*{}*.[log|txt]'", dir.path, dir.filenamecontains);
-                    match group_and_compress_files(&dir.path, &dir.filenamecontains) {
-                        Ok(_) => info!("Completed file compression"),
-                        Err(e) => error!("There was an issue compressing the files: {}", e),
+fn migrate_one(path: &Path) -> bool {
+    match migrate_file(path) {
+        Ok(Some(version)) => {
+            println!("Migrated '{}' from version {} to {}, the old file was kept as '{}.v{}.bak'", path.display(), version, CURRENT_VERSION, path.display(), version);
+            true
+        },
+        Ok(None) => {
+            println!("'{}' is already at version {}", path.display(), CURRENT_VERSION);
+            true
+        },
+        Err(e) => {
+            eprintln!("'{}' {}", path.display(), e);
+            false
+        },
+    }
+}
 
-                    }
-                }else {
-                    info!("Skipping File Compression for FilePath '{}\\*{}*.[log|txt]' because compress setting is false", dir.path, dir.filenamecontains)
-                }
+// Rewrite the config file and every file it includes in the current schema
+fn migrate_config(config_path: &Path) -> ExitStatus {
 
-                // Move to path if it is set and exists
-                let path = Path::new(&dir.movetopath);
-                if path.is_dir() {
-                   
-                    // Remove log files to movetopath
-                    info!("Moving files to '{}' older then today from FilePath '{}\\*{}*.[log|txt|zip]'", dir.movetopath, dir.path, dir.filenamecontains);
-                    match move_files_except_today(&dir.path, &dir.movetopath, &dir.filenamecontains) {
-                        Ok(_) => info!("Completed file move"),
-                        Err(e) => error!("There was an issue moving the files: {}", e),
-
-                    }
-
-                    // Remove old log files in movetopath
-                    info!("Removing files with a date modified older then {} days for FilePath '{}\\*{}*.[log|txt|zip]'", dir.retentionindays, dir.movetopath, dir.filenamecontains);
-                    match remove_old_files(&dir.movetopath, &dir.filenamecontains, &dir.retentionindays) {
-                        Ok(_) => info!("Completed file retention"),
-                        Err(e) => error!("There was an issue removing the files: {}", e),
-                    
-                    }
-
-                } else {
-                    info!("Skipping moving logs to movetopath setting because directory does not exist or blank.");
+    if !migrate_one(config_path) {
+        return ExitStatus::ConfigError;
+    }
 
-                }
+    // The include list is only known once the main file loads
+    let config_file = match load_config(config_path) {
+        Ok(config_file) => config_file,
+        Err(config_errors) => {
+            eprintln!("{}", AppError::Config(config_errors));
+            return ExitStatus::ConfigError;
+        }
+    };
 
-            }
+    let mut migrated = true;
+    for include_file in config_file.files.iter().skip(1) {
+        migrated &= migrate_one(include_file);
+    }
+
+    if migrated { ExitStatus::Success } else { ExitStatus::ConfigError }
+}
+
+#[derive(Serialize)]
+struct RulePlan<'a> {
+    rule: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    path: &'a str,
+    filenamecontains: &'a str,
+    #[serde(flatten)]
+    outcome: &'a Outcome,
+    status_file: Option<&'a PathBuf>,
+}
+
+fn print_plan_text(plan: &RulePlan) {
+
+    println!("Rule {}: {}", plan.rule, rule_label(plan.name, plan.path, plan.filenamecontains));
+
+    for file in &plan.outcome.deleted {
+        println!("  Delete '{}' ({} bytes)", file.path.display(), file.bytes);
+    }
+    for archive in &plan.outcome.compressed {
+        println!("  Compress {} file(s) into '{}'", archive.files.len(), archive.path.display());
+        for file in &archive.files {
+            println!("    '{}' ({} bytes)", file.path.display(), file.bytes);
+        }
+    }
+    for file in &plan.outcome.moved {
+        println!("  Move '{}' to '{}'", file.from.display(), file.to);
+    }
+    if let Some(status_file) = plan.status_file {
+        println!("  Write status file '{}'", status_file.display());
+    }
+    for unchecked in &plan.outcome.unchecked {
+        println!("  Not checked: {}", unchecked);
+    }
+    for error in &plan.outcome.errors {
+        println!("  Error: {}", error);
+    }
+    if plan.outcome.deleted.is_empty() && plan.outcome.compressed.is_empty() && plan.outcome.moved.is_empty() {
+        println!("  Nothing to do");
+    }
+}
+
+// Work out every action for the selected rules without changing anything on disk
+fn plan(config_file: &ConfigFile, config_path: &Path, selection: &RuleSelection, format: PlanFormat) -> ExitStatus {
+
+    // Keep the terminal output for the plan itself, the log only shows problems
+    let _ = TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto);
+
+    let config_errors = validate_config(config_file);
+    for config_error in &config_errors {
+        error!("{}", config_error);
+    }
 
-                // Stopping Tasks
-                endtasks(start_time);
+    let mut selected = Vec::new();
+    let mut matched = false;
+    let mut status = ExitStatus::Success;
+    for (index, dir) in config_file.directories.directory.iter().enumerate() {
+        if !selection.selects(index, dir.name.as_deref(), &dir.path, &dir.filenamecontains) {
+            continue;
+        }
+        matched = true;
 
+        if has_rule_errors(&config_errors, index) {
+            status = ExitStatus::ConfigError;
+            continue;
         }
-        Err(e) => println!("Failed to load toml config: {}", e),
 
+        selected.push((index, dir));
+    }
+
+    // Fail the same way the real run would when --only picks nothing
+    if !matched {
+        eprintln!("{}", AppError::Config(vec![no_matching_rules(selection)]));
+        return ExitStatus::ConfigError;
     }
-   
 
-}
\ No newline at end of file
+    // Show the archives the last run left unfinished being added to, as the real run would
+    let run_state = state::load(&state::state_path(config_path, &config_file.application));
+    let policy = Policy::builder()
+        .rules(selected.iter().map(|(_, dir)| (*dir).clone()))
+        .pending_archives(run_state.pendingarchives)
+        .dry_run(true)
+        .build();
+    let policy = match policy {
+        Ok(policy) => policy,
+        Err(config_errors) => {
+            eprintln!("{}", AppError::Config(config_errors));
+            return ExitStatus::ConfigError;
+        }
+    };
+    let report = Engine::new().run(&policy);
+
+    let plans: Vec<RulePlan> = report.rules.iter()
+        .map(|rule_report| {
+            let (index, dir) = selected[rule_report.rule];
+            RulePlan {
+                rule: index + 1,
+                name: dir.name.as_deref(),
+                path: &dir.path,
+                filenamecontains: &dir.filenamecontains,
+                outcome: &rule_report.outcome,
+                status_file: rule_report.status_file.as_ref(),
+            }
+        })
+        .collect();
+
+    match format {
+        PlanFormat::Text => plans.iter().for_each(print_plan_text),
+        PlanFormat::Json => match serde_json::to_string_pretty(&plans) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to write the plan as JSON: {}", e);
+                return ExitStatus::TotalFailure;
+            }
+        },
+    }
+
+    status
+}
+
+fn list_rules(config_file: &ConfigFile) -> ExitStatus {
+
+    for (index, dir) in config_file.directories.directory.iter().enumerate() {
+        println!("{}\tname={}\tpath={}\tfilenamecontains={}\tretentionindays={}\tcompress={}\tdestination={}\tschedule={}\tsource={}",
+            index + 1, dir.name.as_deref().unwrap_or_default(), dir.path, dir.filenamecontains, dir.retentionindays, dir.compress, destination_label(dir),
+            daemon::rule_schedule(dir, config_file), dir.source.display());
+    }
+
+    ExitStatus::Success
+}
+
+fn main() -> ExitCode {
+
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Run(RunArgs::default()));
+
+    if let Command::Version = command {
+        println!("{} v{}", APP_NAME, env!("CARGO_PKG_VERSION"));
+        return ExitStatus::Success.into();
+    }
+
+    // Migration has to work on files the current loader might reject
+    if let Command::MigrateConfig = command {
+        return migrate_config(&cli.config).into();
+    }
+
+    // Load config file
+    let config_file = match load_config(&cli.config) {
+        Ok(config_file) => config_file,
+        Err(config_errors) => {
+            let e = AppError::Config(config_errors);
+            eprintln!("{}", e);
+            return e.exit_status().into();
+        }
+    };
+
+    let result = match command {
+        Command::Run(args) if args.dry_run => Ok(plan(&config_file, &cli.config, &args.selection, PlanFormat::Text)),
+        Command::Run(args) => run(&config_file, &cli.config, &args.selection, &args.lock),
+        Command::Daemon(args) => daemon::daemon(&cli.config, config_file, &args),
+        Command::Validate => Ok(validate(&config_file)),
+        Command::Plan(args) => Ok(plan(&config_file, &cli.config, &args.selection, args.format)),
+        Command::ListRules => Ok(list_rules(&config_file)),
+        Command::Version | Command::MigrateConfig => unreachable!(),
+    };
+
+    match result {
+        Ok(status) => status.into(),
+        Err(e) => {
+            // There is no logger to write to if it failed to start, or yet when the lock is held
+            match e {
+                AppError::Logger(_) | AppError::Locked(_) => eprintln!("{}", e),
+                _ => error!("{}", e),
+            }
+            e.exit_status().into()
+        }
+    }
+
+}
@@ -0,0 +1,213 @@
+use log::{info, warn};
+use std::{collections::HashMap, fs::{self, File}, io::Write, path::Path};
+
+use crate::{error::{LogRcError, WithPath}, summary::{DirectorySummary, RunSummary}};
+
+const COUNTERS: [(&str, &str); 5] = [
+    ("logrc_runs_total", "Number of runs that processed this directory rule."),
+    ("logrc_run_failures_total", "Number of runs where this directory rule had errors."),
+    ("logrc_files_processed_total", "Files compressed, moved or deleted."),
+    ("logrc_bytes_processed_total", "Bytes compressed, moved or deleted."),
+    ("logrc_bytes_reclaimed_total", "Bytes freed by deleting and compressing files."),
+];
+
+const GAUGES: [(&str, &str); 6] = [
+    ("logrc_last_run_timestamp_seconds", "Unix time the directory rule last finished."),
+    ("logrc_last_run_success", "1 if the last run of the directory rule had no errors."),
+    ("logrc_last_run_files_processed", "Files compressed, moved or deleted by the last run."),
+    ("logrc_last_run_bytes_reclaimed", "Bytes freed by the last run."),
+    ("logrc_archive_count", "Archives kept for the directory rule."),
+    ("logrc_archive_bytes", "Total size of the archives kept for the directory rule."),
+];
+
+// Prometheus label values need backslashes, quotes and new lines escaped
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Rules without a name get an empty rule label, which Prometheus treats the same as no label
+fn labels(directory: &DirectorySummary) -> String {
+    format!("{{rule=\"{}\",path=\"{}\",filenamecontains=\"{}\"}}",
+        escape_label(directory.name.as_deref().unwrap_or_default()), escape_label(&directory.path), escape_label(&directory.filenamecontains))
+}
+
+// Every series in the last metrics file by metric name, with its labels and value. Rules that are not part of
+// this run keep their series, so a run of only some rules does not look like the others stopped reporting
+fn read_previous_series(contents: &str) -> HashMap<String, Vec<(String, f64)>> {
+    let mut series = HashMap::new();
+
+    for line in contents.lines().filter(|line| !line.starts_with('#')) {
+        if let Some((name_and_labels, value)) = line.rsplit_once(' ') {
+            if let (Some(start), Ok(value)) = (name_and_labels.find('{'), value.parse::<f64>()) {
+                let (name, labels) = name_and_labels.split_at(start);
+                series.entry(name.to_string()).or_insert_with(Vec::new).push((labels.to_string(), value));
+            }
+        }
+    }
+
+    series
+}
+
+fn counter_values(directory: &DirectorySummary) -> [f64; 5] {
+    let summary = &directory.summary;
+    [
+        1.0,
+        if summary.errors > 0 { 1.0 } else { 0.0 },
+        summary.files_processed() as f64,
+        summary.bytes_processed() as f64,
+        summary.bytes_reclaimed() as f64,
+    ]
+}
+
+fn gauge_values(directory: &DirectorySummary) -> [f64; 6] {
+    let summary = &directory.summary;
+    [
+        directory.finished_timestamp as f64,
+        if summary.errors == 0 { 1.0 } else { 0.0 },
+        summary.files_processed() as f64,
+        summary.bytes_reclaimed() as f64,
+        directory.archive_count as f64,
+        directory.archive_bytes as f64,
+    ]
+}
+
+// Build the metrics file contents from this run and the contents of the last metrics file
+fn render_metrics(previous: &str, summary: &RunSummary) -> String {
+
+    let previous = read_previous_series(previous);
+    let current: Vec<String> = summary.directories.iter().map(labels).collect();
+    let mut content = String::new();
+
+    for (index, (name, help)) in COUNTERS.iter().enumerate() {
+        content.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n", name, help, name));
+        let previous = previous.get(*name).map(Vec::as_slice).unwrap_or_default();
+        for (directory, labels) in summary.directories.iter().zip(&current) {
+            let value = previous.iter().find(|(previous_labels, _)| previous_labels == labels).map(|(_, value)| *value).unwrap_or(0.0) + counter_values(directory)[index];
+            content.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+        for (labels, value) in previous.iter().filter(|(labels, _)| !current.contains(labels)) {
+            content.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    }
+
+    // Gauges of rules that did not run keep describing their last run, such as rules on another schedule in daemon mode
+    for (index, (name, help)) in GAUGES.iter().enumerate() {
+        content.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+        for (directory, labels) in summary.directories.iter().zip(&current) {
+            content.push_str(&format!("{}{} {}\n", name, labels, gauge_values(directory)[index]));
+        }
+        for (labels, value) in previous.get(*name).into_iter().flatten().filter(|(labels, _)| !current.contains(labels)) {
+            content.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    }
+
+    content
+}
+
+pub fn write_metrics_file(file_path: &str, summary: &RunSummary) -> Result<(), LogRcError> {
+
+    // A missing or unreadable file starts every counter from zero
+    let content = render_metrics(&fs::read_to_string(file_path).unwrap_or_default(), summary);
+
+    // Write to a temp file and rename it so node_exporter never reads a half written file
+    if let Some(parent) = Path::new(file_path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_path(parent)?;
+    }
+    if !file_path.ends_with(".prom") {
+        warn!("[application]metricsfile setting '{}' does not end with .prom and will be ignored by the node_exporter textfile collector", file_path);
+    }
+
+    let temp_path = format!("{}.{}.tmp", file_path, std::process::id());
+    {
+        let mut file = File::create(&temp_path).with_path(&temp_path)?;
+        file.write_all(content.as_bytes()).and_then(|_| file.sync_all()).with_path(&temp_path)?;
+    }
+    fs::rename(&temp_path, file_path).with_path(file_path)?;
+    info!("Created a metrics file at '{}'", file_path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::Summary;
+
+    fn directory(name: &str, files_deleted: usize, errors: usize, finished_timestamp: i64) -> DirectorySummary {
+        DirectorySummary {
+            name: Some(name.to_string()),
+            path: format!("/var/log/{}", name),
+            filenamecontains: name.to_string(),
+            finished: String::new(),
+            finished_timestamp,
+            archive_count: 2,
+            archive_bytes: 300,
+            summary: Summary { files_deleted, bytes_deleted: files_deleted as u64 * 100, errors, ..Summary::default() },
+        }
+    }
+
+    fn run(directories: Vec<DirectorySummary>) -> RunSummary {
+        RunSummary::new(String::new(), String::new(), 0, directories)
+    }
+
+    fn value(contents: &str, name: &str, rule: &str) -> Option<f64> {
+        read_previous_series(contents).get(name)?.iter()
+            .find(|(labels, _)| labels.contains(&format!("rule=\"{}\"", rule)))
+            .map(|(_, value)| *value)
+    }
+
+    #[test]
+    fn first_run_starts_counters_from_zero() {
+        let contents = render_metrics("", &run(vec![directory("app", 3, 0, 1000)]));
+
+        assert!(contents.contains("# TYPE logrc_runs_total counter\n"));
+        assert!(contents.contains("logrc_files_processed_total{rule=\"app\",path=\"/var/log/app\",filenamecontains=\"app\"} 3\n"));
+        assert_eq!(value(&contents, "logrc_runs_total", "app"), Some(1.0));
+        assert_eq!(value(&contents, "logrc_run_failures_total", "app"), Some(0.0));
+        assert_eq!(value(&contents, "logrc_bytes_reclaimed_total", "app"), Some(300.0));
+        assert_eq!(value(&contents, "logrc_last_run_success", "app"), Some(1.0));
+        assert_eq!(value(&contents, "logrc_archive_bytes", "app"), Some(300.0));
+    }
+
+    #[test]
+    fn counters_add_to_the_last_file_and_gauges_are_replaced() {
+        let first = render_metrics("", &run(vec![directory("app", 3, 0, 1000)]));
+        let second = render_metrics(&first, &run(vec![directory("app", 2, 1, 2000)]));
+
+        assert_eq!(value(&second, "logrc_runs_total", "app"), Some(2.0));
+        assert_eq!(value(&second, "logrc_run_failures_total", "app"), Some(1.0));
+        assert_eq!(value(&second, "logrc_files_processed_total", "app"), Some(5.0));
+        assert_eq!(value(&second, "logrc_last_run_files_processed", "app"), Some(2.0));
+        assert_eq!(value(&second, "logrc_last_run_success", "app"), Some(0.0));
+        assert_eq!(value(&second, "logrc_last_run_timestamp_seconds", "app"), Some(2000.0));
+    }
+
+    #[test]
+    fn partial_run_keeps_the_series_of_rules_that_did_not_run() {
+        let first = render_metrics("", &run(vec![directory("app", 3, 0, 1000), directory("web", 4, 0, 1000)]));
+        let partial = render_metrics(&first, &run(vec![directory("web", 1, 0, 2000)]));
+
+        // Every series of the rule that did not run is carried over as it was
+        for (name, _) in COUNTERS.iter().chain(GAUGES.iter()) {
+            assert_eq!(value(&partial, name, "app"), value(&first, name, "app"), "{}", name);
+        }
+        assert_eq!(value(&partial, "logrc_files_processed_total", "web"), Some(5.0));
+        assert_eq!(value(&partial, "logrc_last_run_timestamp_seconds", "web"), Some(2000.0));
+
+        // and each series is written once
+        assert_eq!(partial.lines().filter(|line| line.starts_with("logrc_runs_total{")).count(), 2);
+    }
+
+    #[test]
+    fn previous_series_skip_comments_and_unreadable_lines() {
+        let series = read_previous_series("# HELP logrc_runs_total x\nlogrc_runs_total{rule=\"a b\"} 4\nnot a metric\nlogrc_runs_total{rule=\"c\"} nan-ish\n");
+        assert_eq!(series["logrc_runs_total"], vec![("{rule=\"a b\"}".to_string(), 4.0)]);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let mut dir = directory("app", 0, 0, 0);
+        dir.path = r#"C:\logs\"app""#.to_string();
+        assert_eq!(labels(&dir), r#"{rule="app",path="C:\\logs\\\"app\"",filenamecontains="app"}"#);
+    }
+}
@@ -0,0 +1,238 @@
+use std::{fs, io, path::{Path, PathBuf}};
+use toml::{Table, Value};
+use toml_edit::{ArrayOfTables, DocumentMut, Item};
+
+// Schema versions
+// 1: rules in a [directories] table as directory = [...], movetopath = "" for rules that do not move. No version key
+// 2: version = 2, rules as [[directory]] tables, movetopath left out for rules that do not move
+pub const CURRENT_VERSION: i64 = 2;
+
+// The schema version a config file was written for, files from before versioning are version 1
+pub fn config_version(root: &Table) -> Result<i64, String> {
+    match root.get("version") {
+        None => Ok(1),
+        Some(Value::Integer(version)) if (1..=CURRENT_VERSION).contains(version) => Ok(*version),
+        Some(Value::Integer(version)) => Err(format!("version {} is not supported, the newest version is {}", version, CURRENT_VERSION)),
+        Some(_) => Err("should be a whole number".to_string()),
+    }
+}
+
+// Upgrade a parsed config file to the current schema so loading only has to deal with one layout
+pub fn migrate(root: &mut Table, version: i64) {
+    if version < 2 {
+        migrate_v1_rules(root);
+    }
+    root.insert("version".to_string(), Value::Integer(CURRENT_VERSION));
+}
+
+fn migrate_v1_rules(root: &mut Table) {
+
+    // Move directories.directory to directory, anything else left in [directories] is reported as an unknown key
+    if let Some(Value::Table(mut directories)) = root.remove("directories") {
+        if let Some(rules) = directories.remove("directory") {
+            root.insert("directory".to_string(), rules);
+        }
+        if !directories.is_empty() {
+            root.insert("directories".to_string(), Value::Table(directories));
+        }
+    }
+
+    let clear_movetopath = |table: &mut Table| {
+        if table.get("movetopath").and_then(Value::as_str) == Some("") {
+            table.remove("movetopath");
+        }
+    };
+
+    if let Some(Value::Array(rules)) = root.get_mut("directory") {
+        rules.iter_mut().filter_map(Value::as_table_mut).for_each(clear_movetopath);
+    }
+    if let Some(Value::Table(defaults)) = root.get_mut("defaults") {
+        clear_movetopath(defaults);
+    }
+    if let Some(Value::Table(profiles)) = root.get_mut("profiles") {
+        profiles.iter_mut().filter_map(|(_, profile)| profile.as_table_mut()).for_each(clear_movetopath);
+    }
+}
+
+// Rewrite a config file in the current schema, keeping comments where the layout allows.
+// Returns the version the file was written for, or None when it was already current
+pub fn migrate_file(path: &Path) -> io::Result<Option<i64>> {
+
+    let contents = fs::read_to_string(path)?;
+    let root: Table = toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse: {}", e)))?;
+    let version = config_version(&root).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    let mut document: DocumentMut = contents.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse: {}", e)))?;
+    if version < 2 {
+        migrate_v1_document(&mut document);
+    }
+    document.insert("version", toml_edit::value(CURRENT_VERSION));
+
+    // Keep the old file next to the new one
+    let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+    fs::copy(path, &backup)?;
+    fs::write(path, document.to_string())?;
+
+    Ok(Some(version))
+}
+
+fn clear_empty_movetopath(table: &mut dyn toml_edit::TableLike) {
+    if table.get("movetopath").and_then(Item::as_str) == Some("") {
+        table.remove("movetopath");
+    }
+}
+
+fn migrate_v1_document(document: &mut DocumentMut) {
+
+    if let Some(defaults) = document.get_mut("defaults").and_then(Item::as_table_like_mut) {
+        clear_empty_movetopath(defaults);
+    }
+    if let Some(profiles) = document.get_mut("profiles").and_then(Item::as_table_like_mut) {
+        for (_, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_table_like_mut() {
+                clear_empty_movetopath(profile);
+            }
+        }
+    }
+
+    let directories = match document.get_mut("directories").and_then(Item::as_table_mut) {
+        Some(directories) => directories,
+        None => return,
+    };
+
+    // Comments written above [directories] and above directory = [...] go above the first [[directory]]
+    let mut comments = directories.decor().prefix().and_then(|prefix| prefix.as_str()).unwrap_or_default().to_string();
+    if let Some(key) = directories.key("directory") {
+        comments.push_str(key.leaf_decor().prefix().and_then(|prefix| prefix.as_str()).unwrap_or_default());
+    }
+
+    let mut rules = match directories.remove("directory") {
+        Some(Item::ArrayOfTables(rules)) => rules,
+        Some(Item::Value(toml_edit::Value::Array(rules))) => rules.into_iter()
+            .filter_map(|rule| match rule {
+                toml_edit::Value::InlineTable(rule) => Some(rule.into_table()),
+                _ => None,
+            })
+            .collect(),
+        _ => ArrayOfTables::new(),
+    };
+
+    // Drop [directories] once it is empty
+    if directories.is_empty() {
+        document.remove("directories");
+    }
+
+    for (index, rule) in rules.iter_mut().enumerate() {
+        rule.fmt();
+        clear_empty_movetopath(rule);
+        let prefix = if index == 0 { format!("\n{}", comments.trim_start_matches('\n')) } else { "\n".to_string() };
+        rule.decor_mut().set_prefix(prefix);
+    }
+    document.insert("directory", Item::ArrayOfTables(rules));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = r#"
+[application]
+logretentionindays = 3
+
+[defaults]
+movetopath = ""
+
+[profiles.iis]
+filenamecontains = "ex"
+movetopath = ""
+
+[directories]
+directory = [
+    { path = "/var/log/app", filenamecontains = "app", retentionindays = 7, movetopath = "" },
+    { path = "/var/log/web", filenamecontains = "web", retentionindays = 7, movetopath = "/archive" },
+]
+"#;
+
+    const V2: &str = r#"
+version = 2
+
+[application]
+logretentionindays = 3
+
+[defaults]
+
+[profiles.iis]
+filenamecontains = "ex"
+
+[[directory]]
+path = "/var/log/app"
+filenamecontains = "app"
+retentionindays = 7
+
+[[directory]]
+path = "/var/log/web"
+filenamecontains = "web"
+retentionindays = 7
+movetopath = "/archive"
+"#;
+
+    fn migrated(contents: &str) -> Table {
+        let mut root: Table = toml::from_str(contents).unwrap();
+        let version = config_version(&root).unwrap();
+        migrate(&mut root, version);
+        root
+    }
+
+    #[test]
+    fn v1_rules_match_the_v2_layout() {
+        assert_eq!(migrated(V1), toml::from_str::<Table>(V2).unwrap());
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut once: Table = toml::from_str(V1).unwrap();
+        migrate_v1_rules(&mut once);
+        let mut twice = once.clone();
+        migrate_v1_rules(&mut twice);
+        assert_eq!(once, twice);
+        assert_eq!(migrated(V2), toml::from_str::<Table>(V2).unwrap());
+    }
+
+    #[test]
+    fn unknown_keys_in_directories_are_kept_for_reporting() {
+        let mut root: Table = toml::from_str("[directories]\ndirectory = []\nstray = 1\n").unwrap();
+        migrate_v1_rules(&mut root);
+        assert_eq!(root["directories"]["stray"].as_integer(), Some(1));
+        assert!(root["directory"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn versions_are_checked() {
+        assert_eq!(config_version(&toml::from_str("").unwrap()), Ok(1));
+        assert_eq!(config_version(&toml::from_str("version = 2").unwrap()), Ok(2));
+        assert!(config_version(&toml::from_str("version = 3").unwrap()).is_err());
+        assert!(config_version(&toml::from_str("version = \"2\"").unwrap()).is_err());
+    }
+
+    #[test]
+    fn migrated_file_round_trips_to_the_same_config() {
+        let dir = std::env::temp_dir().join(format!("logrc-migrate-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("LogRC.toml");
+        fs::write(&path, V1).unwrap();
+
+        assert_eq!(migrate_file(&path).unwrap(), Some(1));
+        let rewritten = fs::read_to_string(&path).unwrap();
+        assert_eq!(toml::from_str::<Table>(&rewritten).unwrap(), migrated(V1));
+        assert_eq!(fs::read_to_string(dir.join("LogRC.toml.v1.bak")).unwrap(), V1);
+
+        // The rewritten file is current, so a second migration leaves it alone
+        assert_eq!(migrate_file(&path).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), rewritten);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use log::{info, warn, error};
+use std::{path::Path, time::Duration};
+
+use crate::{LogRcError, RetryError};
+
+// Hooks the engine calls as it works through a rule, for progress displays in embedding applications.
+// Every hook does nothing unless it is overridden, LogObserver writes them to the log
+pub trait Observer: Send + Sync {
+    // A file matched the rule and is being looked at
+    fn on_file_scanned(&self, _path: &Path) {}
+
+    // A file was left for a later run, action is "compressing", "moving" or "uploading"
+    fn on_file_skipped(&self, _path: &Path, _action: &str, _reason: &str) {}
+
+    // An archive is about to be written, or in a dry run would be
+    fn on_archive_started(&self, _archive: &Path, _files: usize, _dry_run: bool) {}
+
+    // Called after each file is added to the archive, with the bytes read from the files so far
+    fn on_archive_progress(&self, _archive: &Path, _file: &Path, _bytes_done: u64, _bytes_total: u64) {}
+
+    // The archive was written, appending is true when it carried on with one an earlier run left unfinished
+    fn on_archive_finished(&self, _archive: &Path, _files: usize, _appending: bool) {}
+
+    fn on_file_deleted(&self, _path: &Path, _dry_run: bool) {}
+
+    // to is a local path or an sftp:// URL
+    fn on_file_moved(&self, _from: &Path, _to: &str, _dry_run: bool) {}
+
+    // A file operation failed after any retries, the run carries on with the next file.
+    // operation reads like "removing file" or "creating zip file"
+    fn on_error(&self, _operation: &str, _path: &Path, _error: &RetryError) {}
+
+    // A step for a rule such as retention or compression is starting, message says what it works on
+    fn on_step_started(&self, _message: &str) {}
+
+    // A step for a rule is left out, such as compression when compress is false
+    fn on_step_skipped(&self, _message: &str) {}
+
+    // A step for a rule such as retention or compression finished
+    fn on_step_finished(&self, _message: &str) {}
+
+    // A step for a rule could not run at all, such as when the directory can not be read
+    fn on_step_failed(&self, _message: &str, _error: &LogRcError) {}
+
+    // The run's time or file budget ran out, message says what is left for the next run
+    fn on_budget_exhausted(&self, _message: &str) {}
+
+    // A file operation failed with a transient error and is tried again after backoff.
+    // attempt is the try that failed, out of attempts
+    fn on_retry(&self, _operation: &str, _path: &Path, _attempt: u32, _attempts: u32, _backoff: Duration, _error: &LogRcError) {}
+}
+
+// The observer the engine uses unless it is given another one, it logs every event
+#[derive(Default)]
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn on_file_skipped(&self, path: &Path, action: &str, reason: &str) {
+        info!("Not {} file: {:?} because {}", action, path.file_name().unwrap_or_default(), reason);
+    }
+
+    fn on_archive_started(&self, archive: &Path, files: usize, dry_run: bool) {
+        if dry_run {
+            info!("Would create zip file: '{}' from {} file(s)", archive.display(), files);
+        }
+    }
+
+    fn on_archive_finished(&self, archive: &Path, files: usize, appending: bool) {
+        if appending {
+            info!("Added {} file(s) to zip file: '{}'", files, archive.display());
+        } else {
+            info!("Created zip file: '{}'", archive.display());
+        }
+    }
+
+    fn on_file_deleted(&self, path: &Path, dry_run: bool) {
+        if dry_run {
+            info!("Would remove file: '{}'", path.display());
+        } else {
+            info!("Removed file: '{}'", path.display());
+        }
+    }
+
+    fn on_file_moved(&self, from: &Path, to: &str, dry_run: bool) {
+        if dry_run {
+            info!("Would move file: '{}' to '{}'", from.display(), to);
+        } else {
+            info!("Moved file: '{}' to '{}'", from.display(), to);
+        }
+    }
+
+    fn on_error(&self, operation: &str, path: &Path, error: &RetryError) {
+        error!("Error {} {}: {}", operation, path.display(), error);
+    }
+
+    fn on_step_started(&self, message: &str) {
+        info!("{}", message);
+    }
+
+    fn on_step_skipped(&self, message: &str) {
+        info!("{}", message);
+    }
+
+    fn on_step_finished(&self, message: &str) {
+        info!("{}", message);
+    }
+
+    fn on_step_failed(&self, message: &str, error: &LogRcError) {
+        error!("{}: {}", message, error);
+    }
+
+    fn on_budget_exhausted(&self, message: &str) {
+        info!("{}", message);
+    }
+
+    fn on_retry(&self, operation: &str, path: &Path, attempt: u32, attempts: u32, backoff: Duration, error: &LogRcError) {
+        warn!("Could not {} '{}', trying again in {} ms (attempt {} of {}): {}", operation, path.display(), backoff.as_millis(), attempt, attempts, error.reason());
+    }
+}
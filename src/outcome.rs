@@ -0,0 +1,141 @@
+use serde::Serialize;
+use std::{collections::BTreeSet, path::{Path, PathBuf}};
+
+use crate::retry::RetryError;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ArchiveRecord {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub files: Vec<FileRecord>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MoveRecord {
+    pub from: PathBuf,
+    pub to: String,
+    pub bytes: u64,
+}
+
+// A file that still could not be handled after retrying, it is left where it is for the next run
+#[derive(Serialize, Clone, Debug)]
+pub struct FailedRecord {
+    pub path: PathBuf,
+    pub operation: String,
+    pub attempts: u32,
+    pub error: String,
+}
+
+// What an operation actually did, so callers can report on it after the fact
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Outcome {
+    #[serde(skip)]
+    pub scanned: BTreeSet<PathBuf>,
+    pub deleted: Vec<FileRecord>,
+    pub compressed: Vec<ArchiveRecord>,
+    pub moved: Vec<MoveRecord>,
+    pub errors: Vec<String>,
+    pub failed: Vec<FailedRecord>,
+    // Steps a dry run could not look into, such as remote retention when the server can not be reached
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unchecked: Vec<String>,
+}
+
+impl Outcome {
+    // Files an earlier step deleted or put into an archive
+    pub fn consumed(&self) -> BTreeSet<PathBuf> {
+        self.deleted.iter().map(|file| file.path.clone())
+            .chain(self.compressed.iter().flat_map(|archive| &archive.files).map(|file| file.path.clone()))
+            .collect()
+    }
+
+    pub fn merge(&mut self, other: Outcome) {
+        self.scanned.extend(other.scanned);
+        self.deleted.extend(other.deleted);
+        self.compressed.extend(other.compressed);
+        self.moved.extend(other.moved);
+        self.errors.extend(other.errors);
+        self.failed.extend(other.failed);
+        self.unchecked.extend(other.unchecked);
+    }
+
+    // Keep the file in the report and count it as an error
+    pub fn fail(&mut self, path: &Path, operation: &str, retry_error: RetryError) {
+        self.errors.push(format!("Could not {} file {}: {}", operation, path.display(), retry_error));
+        self.failed.push(FailedRecord { path: path.to_path_buf(), operation: operation.to_string(), attempts: retry_error.attempts, error: retry_error.error.reason() });
+    }
+
+    // Add the planned outcome of a later step in a dry run. The earlier steps left their files in place,
+    // so drop the actions on files they would have already deleted or archived
+    pub fn merge_planned(&mut self, mut other: Outcome) {
+        let consumed = self.consumed();
+        other.deleted.retain(|file| !consumed.contains(&file.path));
+        for archive in &mut other.compressed {
+            archive.files.retain(|file| !consumed.contains(&file.path));
+        }
+        other.compressed.retain(|archive| !archive.files.is_empty());
+        other.moved.retain(|file| !consumed.contains(&file.from));
+
+        self.merge(other);
+    }
+
+    pub fn files_compressed(&self) -> usize {
+        self.compressed.iter().map(|archive| archive.files.len()).sum()
+    }
+
+    // Size of the original files that went into archives
+    pub fn bytes_before_compression(&self) -> u64 {
+        self.compressed.iter().flat_map(|archive| &archive.files).map(|file| file.bytes).sum()
+    }
+
+    // Size of the archives that were created
+    pub fn bytes_after_compression(&self) -> u64 {
+        self.compressed.iter().map(|archive| archive.bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> FileRecord {
+        FileRecord { path: PathBuf::from(path), bytes: 10 }
+    }
+
+    #[test]
+    fn merge_planned_drops_files_earlier_steps_used_up() {
+        let mut outcome = Outcome { deleted: vec![file("old.log")], ..Outcome::default() };
+        outcome.compressed.push(ArchiveRecord { path: PathBuf::from("day-1.zip"), bytes: 0, files: vec![file("a.log"), file("b.log")] });
+
+        let mut later = Outcome { deleted: vec![file("old.log"), file("older.log")], ..Outcome::default() };
+        later.compressed.push(ArchiveRecord { path: PathBuf::from("again-1.zip"), bytes: 0, files: vec![file("a.log")] });
+        later.compressed.push(ArchiveRecord { path: PathBuf::from("other-1.zip"), bytes: 0, files: vec![file("b.log"), file("c.log")] });
+        later.moved.push(MoveRecord { from: PathBuf::from("a.log"), to: "/archive".to_string(), bytes: 10 });
+        later.moved.push(MoveRecord { from: PathBuf::from("day-1.zip"), to: "/archive".to_string(), bytes: 10 });
+
+        outcome.merge_planned(later);
+
+        let deleted: Vec<_> = outcome.deleted.iter().map(|file| file.path.to_str().unwrap()).collect();
+        assert_eq!(deleted, ["old.log", "older.log"]);
+        let archives: Vec<_> = outcome.compressed.iter().map(|archive| (archive.path.to_str().unwrap(), archive.files.len())).collect();
+        assert_eq!(archives, [("day-1.zip", 2), ("other-1.zip", 1)]);
+        let moved: Vec<_> = outcome.moved.iter().map(|record| record.from.to_str().unwrap()).collect();
+        assert_eq!(moved, ["day-1.zip"]);
+        assert_eq!(outcome.files_compressed(), 3);
+        assert_eq!(outcome.bytes_before_compression(), 30);
+    }
+
+    #[test]
+    fn merge_keeps_everything() {
+        let mut outcome = Outcome { deleted: vec![file("a.log")], ..Outcome::default() };
+        outcome.merge(Outcome { deleted: vec![file("a.log")], errors: vec!["failed".to_string()], ..Outcome::default() });
+        assert_eq!(outcome.deleted.len(), 2);
+        assert_eq!(outcome.errors, ["failed"]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::env;
+
+// Expand ${VAR}, ${VAR:-default}, a leading ~, {hostname} and on Windows %VAR% in a config value.
+// Variables that are not set are left as written and their names are added to undefined
+pub fn expand(value: &str, undefined: &mut Vec<String>) -> String {
+
+    let mut expanded = expand_home(value);
+    expanded = expanded.replace("{hostname}", &hostname());
+    expanded = expand_braced(&expanded, undefined);
+    if cfg!(windows) {
+        expanded = expand_percent(&expanded, undefined);
+    }
+
+    expanded
+}
+
+fn home_dir() -> Option<String> {
+    env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok()
+}
+
+// Only a ~ at the very start that stands for the whole first part of the path
+fn expand_home(value: &str) -> String {
+    match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => match home_dir() {
+            Some(home) => format!("{}{}", home, rest),
+            None => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+fn expand_braced(value: &str, undefined: &mut Vec<String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let end = match closing_brace(after) {
+            Some(end) => end,
+            None => {
+                // No closing brace, keep the rest as it is
+                expanded.push_str(&rest[start..]);
+                return expanded;
+            },
+        };
+
+        let inner = &after[..end];
+        let (name, default) = match inner.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (inner, None),
+        };
+
+        // Like a shell, an empty variable falls back on the default too
+        match (env::var(name).ok().filter(|value| !value.is_empty() || default.is_none()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(&expand_braced(default, undefined)),
+            (None, None) => {
+                undefined.push(name.to_string());
+                expanded.push_str(&rest[start..start + 2 + end + 1]);
+            },
+        }
+
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+// Where the } that closes a ${ is, skipping over any ${...} nested in a default
+fn closing_brace(after: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = after.char_indices().peekable();
+    while let Some((index, character)) = chars.next() {
+        match character {
+            '$' if chars.peek().is_some_and(|(_, next)| *next == '{') => {
+                chars.next();
+                depth += 1;
+            },
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+fn expand_percent(value: &str, undefined: &mut Vec<String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        match after.find('%') {
+            Some(end) if end > 0 => {
+                let name = &after[..end];
+                match env::var(name) {
+                    Ok(value) => expanded.push_str(&value),
+                    Err(_) => {
+                        undefined.push(name.to_string());
+                        expanded.push_str(&rest[start..start + end + 2]);
+                    },
+                }
+                rest = &after[end + 1..];
+            },
+            _ => {
+                expanded.push('%');
+                rest = after;
+            },
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result == 0 {
+        let length = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+        return String::from_utf8_lossy(&buffer[..length]).to_string();
+    }
+    env::var("HOSTNAME").unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test uses its own variable names, tests run side by side in one process
+    #[test]
+    fn expands_set_variables_and_defaults() {
+        env::set_var("LOGRC_TEST_PLACEHOLDER_ROOT", "/var/log");
+        let mut undefined = Vec::new();
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_ROOT}/app", &mut undefined), "/var/log/app");
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_UNSET:-/tmp}/app", &mut undefined), "/tmp/app");
+        assert!(undefined.is_empty());
+    }
+
+    #[test]
+    fn empty_variable_falls_back_on_the_default() {
+        env::set_var("LOGRC_TEST_PLACEHOLDER_EMPTY", "");
+        let mut undefined = Vec::new();
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_EMPTY:-fallback}", &mut undefined), "fallback");
+        assert_eq!(expand("[${LOGRC_TEST_PLACEHOLDER_EMPTY}]", &mut undefined), "[]");
+        assert!(undefined.is_empty());
+    }
+
+    #[test]
+    fn nested_defaults_are_expanded() {
+        env::set_var("LOGRC_TEST_PLACEHOLDER_INNER", "inner");
+        let mut undefined = Vec::new();
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_OUTER:-${LOGRC_TEST_PLACEHOLDER_INNER}}/x", &mut undefined), "inner/x");
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_OUTER:-${LOGRC_TEST_PLACEHOLDER_NONE:-last}}", &mut undefined), "last");
+        assert!(undefined.is_empty());
+
+        assert_eq!(expand("${LOGRC_TEST_PLACEHOLDER_OUTER:-${LOGRC_TEST_PLACEHOLDER_NONE}}", &mut undefined), "${LOGRC_TEST_PLACEHOLDER_NONE}");
+        assert_eq!(undefined, vec!["LOGRC_TEST_PLACEHOLDER_NONE"]);
+    }
+
+    #[test]
+    fn undefined_variables_are_left_and_reported() {
+        let mut undefined = Vec::new();
+        assert_eq!(expand("/logs/${LOGRC_TEST_PLACEHOLDER_MISSING}/app", &mut undefined), "/logs/${LOGRC_TEST_PLACEHOLDER_MISSING}/app");
+        assert_eq!(undefined, vec!["LOGRC_TEST_PLACEHOLDER_MISSING"]);
+    }
+
+    #[test]
+    fn unclosed_brace_is_kept() {
+        let mut undefined = Vec::new();
+        assert_eq!(expand("/logs/${LOGRC_TEST_PLACEHOLDER_OPEN", &mut undefined), "/logs/${LOGRC_TEST_PLACEHOLDER_OPEN");
+        assert!(undefined.is_empty());
+    }
+
+    #[test]
+    fn hostname_is_filled_in() {
+        let mut undefined = Vec::new();
+        assert_eq!(expand("/logs/{hostname}/app", &mut undefined), format!("/logs/{}/app", hostname()));
+        assert!(!hostname().is_empty());
+    }
+
+    #[test]
+    fn home_is_only_expanded_at_the_start() {
+        let mut undefined = Vec::new();
+        match home_dir() {
+            Some(home) => assert_eq!(expand("~/logs", &mut undefined), format!("{}/logs", home)),
+            None => assert_eq!(expand("~/logs", &mut undefined), "~/logs"),
+        }
+        assert_eq!(expand("~user/logs", &mut undefined), "~user/logs");
+        assert_eq!(expand("/logs/~", &mut undefined), "/logs/~");
+    }
+}
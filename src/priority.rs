@@ -0,0 +1,88 @@
+use log::{info, error};
+
+use crate::config::Application;
+
+// I/O scheduling classes as used by ionice
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+// Read "idle", "besteffort", "besteffort:7" or "realtime:0". The level is 0 (highest) to 7 (lowest)
+pub fn parse_ionice(value: &str) -> Result<(IoClass, u32), String> {
+    let (class, level) = match value.split_once(':') {
+        Some((class, level)) => (class, Some(level)),
+        None => (value, None),
+    };
+
+    let class = match class {
+        "realtime" => IoClass::Realtime,
+        "besteffort" => IoClass::BestEffort,
+        "idle" => IoClass::Idle,
+        _ => return Err(format!("'{}' should be idle, besteffort or realtime with an optional :level", value)),
+    };
+
+    let level = match level {
+        None => 4,
+        Some(level) => match level.parse::<u32>() {
+            Ok(level) if level <= 7 => level,
+            _ => return Err(format!("'{}' should have a level between 0-7", value)),
+        },
+    };
+
+    Ok((class, level))
+}
+
+// Set the CPU and I/O priority from [application]. Problems are logged, the run still goes ahead
+pub fn apply_priority(application: &Application) {
+    if let Some(nice) = application.nice {
+        match set_nice(nice) {
+            Ok(()) => info!("Set CPU priority to nice {}", nice),
+            Err(e) => error!("Failed to set CPU priority to nice {}: {}", nice, e),
+        }
+    }
+
+    if let Some(ionice) = &application.ionice {
+        match parse_ionice(ionice).map_err(std::io::Error::other).and_then(|(class, level)| set_ionice(class, level)) {
+            Ok(()) => info!("Set I/O priority to {}", ionice),
+            Err(e) => error!("Failed to set I/O priority to {}: {}", ionice, e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> std::io::Result<()> {
+    // On Linux this sets the calling thread, which every thread started afterwards inherits
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+    if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(not(unix))]
+fn set_nice(_nice: i32) -> std::io::Result<()> {
+    log::warn!("[application]nice setting is only supported on Linux and Unix");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_ionice(class: IoClass, level: u32) -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+    let class = match class {
+        IoClass::Realtime => 1,
+        IoClass::BestEffort => 2,
+        IoClass::Idle => 3,
+    };
+    let priority = (class << IOPRIO_CLASS_SHIFT) | level;
+
+    let result = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority as libc::c_int) };
+    if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_ionice(_class: IoClass, _level: u32) -> std::io::Result<()> {
+    log::warn!("[application]ionice setting is only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use log::{info, warn, error};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use glob::Pattern;
+use std::{collections::{HashMap, HashSet}, fs, path::{Component, Path, PathBuf}, sync::mpsc::{channel, Receiver}, time::{Duration, Instant}};
+
+use crate::cli::RuleSelection;
+use log_rc::{ConfigFile, load_config, validate_config};
+
+// Editors often write a file in more than one step, so wait for the changes to settle before reloading
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+// Files are watched through their directory so a file that is replaced rather than written in place is still seen
+fn watch_path(file: &Path) -> PathBuf {
+    let parent = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+    match file.file_name() {
+        Some(file_name) => parent.join(file_name),
+        None => parent,
+    }
+}
+
+// The directory an include pattern picks files from and the pattern to match file events against.
+// Patterns that reach into sub directories, such as conf.d/**/*.toml, need the directory watched recursively
+fn include_watch(pattern: &str) -> Option<(PathBuf, RecursiveMode, Pattern)> {
+    let mut directory = PathBuf::new();
+    let mut rest: Vec<Component> = Vec::new();
+    for component in Path::new(pattern).components() {
+        if rest.is_empty() && !component.as_os_str().to_string_lossy().contains(['*', '?', '[']) {
+            directory.push(component);
+        } else {
+            rest.push(component);
+        }
+    }
+
+    // A pattern without wildcards names a single file
+    if rest.is_empty() {
+        let file_name = directory.file_name()?.to_os_string();
+        directory.pop();
+        return include_watch_parts(directory, vec![Component::Normal(&file_name)]);
+    }
+    include_watch_parts(directory, rest)
+}
+
+fn include_watch_parts(directory: PathBuf, rest: Vec<Component>) -> Option<(PathBuf, RecursiveMode, Pattern)> {
+    let directory = if directory.as_os_str().is_empty() { PathBuf::from(".") } else { directory };
+    let directory = fs::canonicalize(directory).ok()?;
+    let mode = if rest.len() > 1 { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    let pattern = Pattern::new(&directory.join(rest.iter().collect::<PathBuf>()).to_string_lossy()).ok()?;
+    Some((directory, mode, pattern))
+}
+
+// Watches the config file, every included file and the include patterns for changes
+pub struct ConfigWatch {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    files: HashSet<PathBuf>,
+    patterns: Vec<Pattern>,
+    changed_at: Option<Instant>,
+}
+
+impl ConfigWatch {
+    pub fn start(config_file: &ConfigFile) -> notify::Result<ConfigWatch> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        let files: HashSet<PathBuf> = config_file.files.iter().map(|file| watch_path(file)).collect();
+        let mut directories: HashMap<PathBuf, RecursiveMode> = files.iter()
+            .filter_map(|file| file.parent())
+            .map(|directory| (directory.to_path_buf(), RecursiveMode::NonRecursive))
+            .collect();
+
+        // Include files added after the config loaded only show up through their pattern
+        let mut patterns = Vec::new();
+        for include_pattern in &config_file.include_patterns {
+            match include_watch(include_pattern) {
+                Some((directory, mode, pattern)) => {
+                    let watched = directories.entry(directory).or_insert(mode);
+                    if mode == RecursiveMode::Recursive {
+                        *watched = mode;
+                    }
+                    patterns.push(pattern);
+                },
+                None => warn!("Can not watch the include pattern '{}' for new files, its directory does not exist", include_pattern),
+            }
+        }
+
+        for (directory, mode) in &directories {
+            watcher.watch(directory, *mode)?;
+        }
+
+        Ok(ConfigWatch { _watcher: watcher, events, files, patterns, changed_at: None })
+    }
+
+    // True once a config file has changed and has been left alone for a moment
+    pub fn changed(&mut self) -> bool {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    if event.paths.iter().any(|path| self.files.contains(path) || self.patterns.iter().any(|pattern| pattern.matches_path(path))) {
+                        self.changed_at = Some(Instant::now());
+                    }
+                },
+                Ok(_) => (),
+                Err(e) => error!("There was an issue watching the config files: {}", e),
+            }
+        }
+
+        match self.changed_at {
+            Some(changed_at) if changed_at.elapsed() >= SETTLE_TIME => {
+                self.changed_at = None;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+fn log_rule_changes(current: &ConfigFile, new: &ConfigFile) {
+    let mut unchanged = true;
+
+    if current.application != new.application {
+        info!("Config reload changed the [application] settings");
+        unchanged = false;
+    }
+
+    for dir in &new.directories.directory {
+        match current.directories.directory.iter().find(|old| old.key() == dir.key()) {
+            None => info!("Config reload added {}", dir.label()),
+            Some(old) if old != dir => info!("Config reload changed {}", dir.label()),
+            Some(_) => continue,
+        }
+        unchanged = false;
+    }
+
+    for old in &current.directories.directory {
+        if !new.directories.directory.iter().any(|dir| dir.key() == old.key()) {
+            info!("Config reload removed {}", old.label());
+            unchanged = false;
+        }
+    }
+
+    if unchanged {
+        info!("Config reload found no changes to the rules");
+    }
+}
+
+// Load and validate the config again. The new config is only returned when it has no problems at all.
+// Startup skips rules with problems because there is nothing else to run, a reload keeps the rules that are
+// running now rather than dropping one over a typo
+pub fn reload(config_path: &Path, current: &ConfigFile, selection: &RuleSelection) -> Option<ConfigFile> {
+    info!("Reloading config '{}'", config_path.display());
+
+    let new = match load_config(config_path) {
+        Ok(new) => new,
+        Err(config_errors) => {
+            config_errors.iter().for_each(|config_error| error!("{}", config_error));
+            warn!("Keeping the current config because the new one failed to load");
+            return None;
+        }
+    };
+
+    let config_errors = validate_config(&new);
+    if !config_errors.is_empty() {
+        config_errors.iter().for_each(|config_error| error!("{}", config_error));
+        warn!("Keeping the current config because the new one has {} problem(s)", config_errors.len());
+        return None;
+    }
+
+    let selected = new.directories.directory.iter().enumerate()
+        .any(|(index, dir)| selection.selects(index, dir.name.as_deref(), &dir.path, &dir.filenamecontains));
+    if !selected {
+        warn!("Keeping the current config because no directory rules in the new one match {}", selection.only.join(", "));
+        return None;
+    }
+
+    log_rule_changes(current, &new);
+    Some(new)
+}
@@ -0,0 +1,193 @@
+use serde::Deserialize;
+use std::{fmt, io::{self, ErrorKind}, path::Path, thread, time::Duration};
+
+use crate::{error::LogRcError, observer::Observer};
+
+// Errors that can clear up by themselves, such as a virus scanner holding a file or a network share dropping out
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TransientError {
+    // Another process has the file locked or open without sharing
+    Locked,
+    PermissionDenied,
+    TimedOut,
+    Interrupted,
+    // The network or a network share went away for a moment
+    Network,
+}
+
+impl TransientError {
+    fn matches(&self, error: &io::Error) -> bool {
+        match self {
+            TransientError::Locked => matches!(error.kind(), ErrorKind::ResourceBusy | ErrorKind::WouldBlock) || is_sharing_violation(error),
+            TransientError::PermissionDenied => error.kind() == ErrorKind::PermissionDenied,
+            TransientError::TimedOut => error.kind() == ErrorKind::TimedOut,
+            TransientError::Interrupted => error.kind() == ErrorKind::Interrupted,
+            TransientError::Network => matches!(error.kind(),
+                ErrorKind::NetworkDown | ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted | ErrorKind::NotConnected | ErrorKind::StaleNetworkFileHandle),
+        }
+    }
+}
+
+// ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION have no ErrorKind of their own
+#[cfg(windows)]
+fn is_sharing_violation(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(32) | Some(33))
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation(_error: &io::Error) -> bool {
+    false
+}
+
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_BACKOFF: u64 = 500;
+pub const DEFAULT_RETRY_ON: [TransientError; 4] = [TransientError::Locked, TransientError::TimedOut, TransientError::Interrupted, TransientError::Network];
+
+// How often to try a file operation and how long to wait in between. The wait doubles after each try
+#[derive(Clone, PartialEq, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub retryon: Vec<TransientError>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: DEFAULT_RETRY_ATTEMPTS,
+            backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF),
+            retryon: DEFAULT_RETRY_ON.to_vec(),
+        }
+    }
+}
+
+// The error from the last try, and how many tries it took to give up
+#[derive(Debug)]
+pub struct RetryError {
+    pub error: LogRcError,
+    pub attempts: u32,
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.attempts > 1 {
+            write!(f, "{} (gave up after {} attempts)", self.error.reason(), self.attempts)
+        } else {
+            write!(f, "{}", self.error.reason())
+        }
+    }
+}
+
+// An error from an operation that is not retried
+impl From<LogRcError> for RetryError {
+    fn from(error: LogRcError) -> RetryError {
+        RetryError { error, attempts: 1 }
+    }
+}
+
+impl From<RetryError> for LogRcError {
+    fn from(retry_error: RetryError) -> LogRcError {
+        retry_error.error
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_transient(&self, error: &LogRcError) -> bool {
+        error.io_error().is_some_and(|error| self.retryon.iter().any(|kind| kind.matches(error)))
+    }
+
+    // Run a file operation, trying again while it fails with a transient error. The observer hears about each retry
+    pub fn run<T>(&self, operation: &str, path: &Path, observer: &dyn Observer, mut attempt: impl FnMut() -> Result<T, LogRcError>) -> Result<T, RetryError> {
+        let mut attempts = 0;
+        let mut backoff = self.backoff;
+        loop {
+            attempts += 1;
+            match attempt() {
+                Ok(value) => return Ok(value),
+                Err(error) if attempts < self.attempts && self.is_transient(&error) => {
+                    observer.on_retry(operation, path, attempts, self.attempts, backoff, &error);
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                },
+                Err(error) => return Err(RetryError { error, attempts }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, sync::Mutex};
+
+    // Records the retries instead of logging them
+    #[derive(Default)]
+    struct Retries(Mutex<Vec<(u32, u32, Duration)>>);
+
+    impl Observer for Retries {
+        fn on_retry(&self, _operation: &str, _path: &Path, attempt: u32, attempts: u32, backoff: Duration, _error: &LogRcError) {
+            self.0.lock().unwrap().push((attempt, attempts, backoff));
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { attempts: 3, backoff: Duration::from_millis(1), retryon: DEFAULT_RETRY_ON.to_vec() }
+    }
+
+    fn failure(kind: ErrorKind) -> LogRcError {
+        LogRcError::io("file.log", io::Error::from(kind))
+    }
+
+    #[test]
+    fn transient_errors_are_tried_again_until_it_works() {
+        let retries = Retries::default();
+        let tries = Cell::new(0);
+        let result = policy().run("open", Path::new("file.log"), &retries, || {
+            tries.set(tries.get() + 1);
+            if tries.get() < 3 { Err(failure(ErrorKind::TimedOut)) } else { Ok(tries.get()) }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(*retries.0.lock().unwrap(), vec![(1, 3, Duration::from_millis(1)), (2, 3, Duration::from_millis(2))]);
+    }
+
+    #[test]
+    fn transient_errors_give_up_after_the_attempts() {
+        let retries = Retries::default();
+        let tries = Cell::new(0);
+        let result: Result<(), RetryError> = policy().run("open", Path::new("file.log"), &retries, || {
+            tries.set(tries.get() + 1);
+            Err(failure(ErrorKind::Interrupted))
+        });
+
+        let retry_error = result.unwrap_err();
+        assert_eq!(retry_error.attempts, 3);
+        assert_eq!(tries.get(), 3);
+        assert_eq!(retries.0.lock().unwrap().len(), 2);
+        assert!(retry_error.to_string().ends_with("(gave up after 3 attempts)"));
+    }
+
+    #[test]
+    fn other_errors_are_not_tried_again() {
+        let retries = Retries::default();
+        let tries = Cell::new(0);
+        let result: Result<(), RetryError> = policy().run("remove", Path::new("file.log"), &retries, || {
+            tries.set(tries.get() + 1);
+            Err(failure(ErrorKind::NotFound))
+        });
+
+        assert_eq!(result.unwrap_err().attempts, 1);
+        assert_eq!(tries.get(), 1);
+        assert!(retries.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_the_configured_kinds_are_transient() {
+        let policy = RetryPolicy { retryon: vec![TransientError::PermissionDenied], ..policy() };
+        assert!(policy.is_transient(&failure(ErrorKind::PermissionDenied)));
+        assert!(!policy.is_transient(&failure(ErrorKind::TimedOut)));
+        assert!(!RetryPolicy::default().is_transient(&failure(ErrorKind::PermissionDenied)));
+    }
+}
@@ -0,0 +1,11 @@
+use cron::Schedule;
+use std::str::FromStr;
+
+// Accept the usual 5 field cron format as well as the 6 and 7 field format with seconds and years
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    Schedule::from_str(&expression).map_err(|e| e.to_string())
+}
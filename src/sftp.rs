@@ -0,0 +1,418 @@
+use log::{warn, debug};
+use std::{fs::{self, File}, io::{self, Read}, net::TcpStream, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use chrono::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, KnownHostFileKind, RenameFlags, Session, Sftp};
+
+use crate::{read_file_date, active::ActiveFileCheck, budget::RunBudget, error::{LogRcError, WithPath}, observer::Observer, retry::RetryPolicy, outcome::{Outcome, FileRecord, MoveRecord}};
+
+const DEFAULT_SFTP_PORT: u16 = 22;
+const TEMP_SUFFIX: &str = ".part";
+const OLD_SUFFIX: &str = ".old";
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct SftpDestination {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: String,
+    pub privatekey: String,
+    pub publickey: Option<String>,
+    pub passphrase: Option<String>,
+    pub knownhosts: Option<String>,
+    // Connect without checking the host key when knownhosts is not set, only for servers on a trusted network
+    #[serde(default)]
+    pub skiphostkeycheck: bool,
+    pub remotepath: String,
+}
+
+// Where errors about the server point to
+fn server_url(destination: &SftpDestination) -> String {
+    format!("sftp://{}:{}", destination.host, destination.port.unwrap_or(DEFAULT_SFTP_PORT))
+}
+
+// Open an authenticated SFTP session using key based auth
+fn connect(destination: &SftpDestination) -> Result<(Session, Sftp), LogRcError> {
+
+    let url = server_url(destination);
+
+    // Refuse to talk to a server that can not be verified unless the config asks for it
+    if destination.knownhosts.is_none() && !destination.skiphostkeycheck {
+        return Err(LogRcError::Config { setting: "sftp.knownhosts".to_string(), message: format!("should be set to verify the host key for '{}', or set sftp.skiphostkeycheck = true", destination.host) });
+    }
+
+    let port = destination.port.unwrap_or(DEFAULT_SFTP_PORT);
+    let tcp = TcpStream::connect((destination.host.as_str(), port)).with_path(&url)?;
+
+    let mut session = Session::new().map_err(io::Error::from).with_path(&url)?;
+    session.set_tcp_stream(tcp);
+    session.handshake().map_err(io::Error::from).with_path(&url)?;
+
+    // Verify the host key against the known_hosts file
+    match &destination.knownhosts {
+        Some(known_hosts_path) => verify_host_key(&session, destination, known_hosts_path)?,
+        None => warn!("[directory]sftp skiphostkeycheck is set, the host key for '{}' will not be verified", destination.host),
+    }
+
+    session.userauth_pubkey_file(
+        &destination.username,
+        destination.publickey.as_deref().map(Path::new),
+        Path::new(&destination.privatekey),
+        destination.passphrase.as_deref(),
+    ).map_err(|e| LogRcError::Config { setting: "sftp.privatekey".to_string(), message: format!("was turned down for '{}@{}': {}", destination.username, destination.host, e) })?;
+
+    if !session.authenticated() {
+        return Err(LogRcError::Config { setting: "sftp.privatekey".to_string(), message: format!("was turned down for '{}@{}'", destination.username, destination.host) });
+    }
+
+    let sftp = session.sftp().map_err(io::Error::from).with_path(&url)?;
+    Ok((session, sftp))
+}
+
+fn verify_host_key(session: &Session, destination: &SftpDestination, known_hosts_path: &str) -> Result<(), LogRcError> {
+
+    let url = server_url(destination);
+    let mut known_hosts = session.known_hosts().map_err(io::Error::from).with_path(&url)?;
+    known_hosts.read_file(Path::new(known_hosts_path), KnownHostFileKind::OpenSSH).map_err(io::Error::from).with_path(known_hosts_path)?;
+
+    let (key, _) = session.host_key().ok_or_else(|| LogRcError::verification(&url, "SFTP server did not send a host key"))?;
+    let port = destination.port.unwrap_or(DEFAULT_SFTP_PORT);
+
+    match known_hosts.check_port(&destination.host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(LogRcError::verification(&url, format!("host key does not match '{}'", known_hosts_path))),
+        CheckResult::NotFound => Err(LogRcError::verification(&url, format!("host key was not found in '{}'", known_hosts_path))),
+        CheckResult::Failure => Err(LogRcError::verification(&url, "failed to check the host key")),
+    }
+}
+
+// Remote paths always use '/' no matter the local platform
+fn remote_join(remote_dir: &str, file_name: &str) -> PathBuf {
+    PathBuf::from(format!("{}/{}", remote_dir.trim_end_matches('/'), file_name))
+}
+
+fn sha256_file(path: &Path) -> Result<String, LogRcError> {
+    let mut file = File::open(path).with_path(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).with_path(path)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Ask the server for a sha256 of the remote file. Returns None when the server does not allow running 'sha256sum'
+fn remote_sha256(session: &Session, remote_path: &Path) -> Option<String> {
+
+    let quoted = format!("'{}'", remote_path.to_string_lossy().replace('\'', "'\\''"));
+    let mut channel = session.channel_session().ok()?;
+    channel.exec(&format!("sha256sum {}", quoted)).ok()?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output).ok()?;
+    channel.wait_close().ok()?;
+
+    if channel.exit_status().ok()? != 0 {
+        return None;
+    }
+
+    output.split_whitespace().next().map(|hash| hash.to_lowercase())
+}
+
+fn upload_file(session: &Session, sftp: &Sftp, local_path: &Path, remote_dir: &str) -> Result<PathBuf, LogRcError> {
+
+    let file_name = local_path.file_name().unwrap().to_string_lossy().to_string();
+    let temp_path = remote_join(remote_dir, &format!("{}{}", file_name, TEMP_SUFFIX));
+    let final_path = remote_join(remote_dir, &file_name);
+
+    // Upload to a temp name so a partial upload is never mistaken for a finished file
+    let local_size = fs::metadata(local_path).with_path(local_path)?.len();
+    {
+        let mut local_file = File::open(local_path).with_path(local_path)?;
+        let mut remote_file = sftp.create(&temp_path).map_err(io::Error::from).with_path(&temp_path)?;
+        io::copy(&mut local_file, &mut remote_file).with_path(&temp_path)?;
+    }
+
+    // Compare the remote size before trusting the upload
+    let remote_size = sftp.stat(&temp_path).map_err(io::Error::from).with_path(&temp_path)?.size.unwrap_or(0);
+    if remote_size != local_size {
+        let _ = sftp.unlink(&temp_path);
+        return Err(LogRcError::verification(&temp_path, format!("remote size {} does not match local size {}", remote_size, local_size)));
+    }
+
+    // Compare checksums when the server supports it
+    match remote_sha256(session, &temp_path) {
+        Some(remote_hash) => {
+            let local_hash = sha256_file(local_path)?;
+            if remote_hash != local_hash {
+                let _ = sftp.unlink(&temp_path);
+                return Err(LogRcError::verification(&temp_path, "remote checksum does not match local checksum"));
+            }
+        },
+        None => debug!("Remote checksum is not supported by '{}', only the size was verified for '{}'", remote_dir, file_name),
+    }
+
+    replace_remote_file(sftp, &temp_path, &final_path)?;
+
+    Ok(final_path)
+}
+
+// Swap the uploaded file in so a copy is always there under one of the names. Servers that only speak
+// SFTP version 3, like OpenSSH, ignore the overwrite flags and refuse to rename over an existing file
+fn replace_remote_file(sftp: &Sftp, temp_path: &Path, final_path: &Path) -> Result<(), LogRcError> {
+
+    let rename_error = match sftp.rename(temp_path, final_path, Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE)) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if sftp.stat(final_path).is_err() {
+        return Err(LogRcError::io(final_path, io::Error::from(rename_error)));
+    }
+
+    // Move the old file aside rather than removing it, and only remove it once the new one is in place
+    let old_path = PathBuf::from(format!("{}{}", final_path.display(), OLD_SUFFIX));
+    let _ = sftp.unlink(&old_path);
+    sftp.rename(final_path, &old_path, None).map_err(io::Error::from).with_path(final_path)?;
+    if let Err(e) = sftp.rename(temp_path, final_path, None) {
+        let _ = sftp.rename(&old_path, final_path, None);
+        return Err(LogRcError::io(final_path, io::Error::from(e)));
+    }
+    if let Err(e) = sftp.unlink(&old_path) {
+        warn!("Could not remove the replaced remote file '{}': {}", old_path.display(), e);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn move_files_to_sftp(source_dir: &str, destination: &SftpDestination, filename_contains: &str, active: &ActiveFileCheck, retry: &RetryPolicy, budget: &RunBudget, observer: &dyn Observer, dry_run: bool) -> Result<Outcome, LogRcError> {
+    let mut outcome = Outcome::default();
+    let today = Local::now().date_naive();
+    let active = active.start();
+
+    // A dry run only needs the local files, so do not connect
+    let connection = if dry_run { None } else { Some(connect(destination)?) };
+
+    for entry in fs::read_dir(source_dir).with_path(source_dir)? {
+        let entry = entry.with_path(source_dir)?;
+        let path = entry.path();
+
+        if path.is_file() {
+            let filename = path.file_name().unwrap().to_string_lossy();
+
+            // Get the file's creation date
+            let Some((metadata, file_date)) = read_file_date(&path, observer, &mut outcome) else { continue };
+
+            if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+
+                // Check if the extension is a log, txt, or zip file
+                if extension == "log" || extension == "txt" || extension == "zip" {
+
+                    // Check if the file was created today
+                    if file_date == today {
+                        observer.on_file_skipped(&path, "uploading", "it was made today");
+                        continue;
+                    }
+
+                    // Check if the filename contains the specified string
+                    if filename.contains(filename_contains) {
+                        outcome.scanned.insert(path.clone());
+                        observer.on_file_scanned(&path);
+                        if let Some(reason) = active.reason(&path, &metadata) {
+                            observer.on_file_skipped(&path, "uploading", &reason);
+                            continue;
+                        }
+                        if !dry_run && !budget.take_file() {
+                            observer.on_budget_exhausted("Run budget is used up, leaving the remaining files to upload for the next run");
+                            break;
+                        }
+                        let remote_path = match &connection {
+                            Some((session, sftp)) => {
+                                // Carry on with the other files, a failed one stays where it is for the next run
+                                let remote_path = match retry.run("upload", &path, observer, || upload_file(session, sftp, &path, &destination.remotepath)) {
+                                    Ok(remote_path) => remote_path,
+                                    Err(e) => {
+                                        observer.on_error("uploading file", &path, &e);
+                                        outcome.fail(&path, "upload", e);
+                                        continue;
+                                    }
+                                };
+                                if let Err(e) = retry.run("remove", &path, observer, || fs::remove_file(&path).with_path(&path)) {
+                                    observer.on_error("removing file", &path, &e);
+                                    outcome.fail(&path, "remove", e);
+                                }
+                                remote_path
+                            },
+                            None => remote_join(&destination.remotepath, &filename),
+                        };
+                        let to = format!("sftp://{}{}", destination.host, remote_path.display());
+                        observer.on_file_moved(&path, &to, dry_run);
+                        outcome.moved.push(MoveRecord { from: path.clone(), to, bytes: metadata.len() });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+pub fn remove_old_sftp_files(destination: &SftpDestination, search_str: &str, days: &u64, retry: &RetryPolicy, observer: &dyn Observer, dry_run: bool) -> Result<Outcome, LogRcError> {
+    let mut outcome = Outcome::default();
+    let now = SystemTime::now();
+    let max_age = Duration::from_secs(*days * 24 * 60 * 60);
+
+    // A dry run should not fail on a host that can not reach the server, it says the remote files were not checked
+    let (_session, sftp) = match connect(destination) {
+        Ok(connection) => connection,
+        Err(e) if dry_run => {
+            outcome.unchecked.push(format!("Remote files in 'sftp://{}{}' older then {} days, could not connect: {}", destination.host, destination.remotepath, days, e.reason()));
+            return Ok(outcome);
+        },
+        Err(e) => return Err(e),
+    };
+
+    for (path, stat) in sftp.readdir(Path::new(&destination.remotepath)).map_err(io::Error::from).with_path(&destination.remotepath)? {
+        if !stat.is_file() {
+            continue;
+        }
+
+        if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+            if file_name.contains(search_str) {
+                if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+                    if extension == "log" || extension == "txt" || extension == "zip" {
+                        outcome.scanned.insert(path.clone());
+                        observer.on_file_scanned(&path);
+                        if let Some(mtime) = stat.mtime {
+                            let modified_time = UNIX_EPOCH + Duration::from_secs(mtime);
+                            if let Ok(duration) = now.duration_since(modified_time) {
+                                if duration > max_age {
+                                    let url = PathBuf::from(format!("sftp://{}{}", destination.host, path.display()));
+                                    if dry_run {
+                                        observer.on_file_deleted(&url, true);
+                                        outcome.deleted.push(FileRecord { path: path.clone(), bytes: stat.size.unwrap_or(0) });
+                                    } else if let Err(e) = retry.run("remove", &path, observer, || sftp.unlink(&path).map_err(io::Error::from).with_path(&path)) {
+                                        observer.on_error("removing remote file", &path, &e);
+                                        outcome.fail(&path, "remove", e);
+                                    } else {
+                                        observer.on_file_deleted(&url, false);
+                                        outcome.deleted.push(FileRecord { path: path.clone(), bytes: stat.size.unwrap_or(0) });
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::LogObserver;
+    use std::env;
+
+    fn destination(knownhosts: Option<&str>, skiphostkeycheck: bool) -> SftpDestination {
+        SftpDestination {
+            host: "127.0.0.1".to_string(),
+            port: Some(1),
+            username: "logrc".to_string(),
+            privatekey: "id_ed25519".to_string(),
+            publickey: None,
+            passphrase: None,
+            knownhosts: knownhosts.map(str::to_string),
+            skiphostkeycheck,
+            remotepath: "/srv/logs".to_string(),
+        }
+    }
+
+    #[test]
+    fn remote_join_uses_forward_slashes() {
+        assert_eq!(remote_join("/srv/logs/", "a.zip"), PathBuf::from("/srv/logs/a.zip"));
+        assert_eq!(remote_join("/srv/logs", "a.zip"), PathBuf::from("/srv/logs/a.zip"));
+    }
+
+    #[test]
+    fn connect_refuses_without_known_hosts() {
+        match connect(&destination(None, false)) {
+            Err(LogRcError::Config { setting, .. }) => assert_eq!(setting, "sftp.knownhosts"),
+            Err(e) => panic!("expected a config error, got {}", e),
+            Ok(_) => panic!("expected a config error"),
+        }
+    }
+
+    #[test]
+    fn connect_goes_ahead_when_host_key_check_is_skipped() {
+        // Port 1 is closed, so getting as far as the connection shows the host key check let it through
+        assert!(matches!(connect(&destination(None, true)), Err(LogRcError::Io { .. })));
+    }
+
+    #[test]
+    fn dry_run_retention_notes_a_server_it_can_not_reach() {
+        let outcome = remove_old_sftp_files(&destination(None, true), "app", &7, &RetryPolicy::default(), &LogObserver, true).unwrap();
+        assert_eq!(outcome.unchecked.len(), 1);
+        assert!(outcome.unchecked[0].starts_with("Remote files in 'sftp://127.0.0.1/srv/logs' older then 7 days, could not connect"));
+        assert!(outcome.errors.is_empty());
+
+        assert!(remove_old_sftp_files(&destination(None, true), "app", &7, &RetryPolicy::default(), &LogObserver, false).is_err());
+    }
+
+    // Runs against a real server such as OpenSSH in a container, set LOGRC_TEST_SFTP_HOST, _PORT, _USER, _KEY,
+    // _KNOWNHOSTS and _DIR then run cargo test -- --ignored
+    fn test_server() -> Option<SftpDestination> {
+        let var = |name: &str| env::var(format!("LOGRC_TEST_SFTP_{}", name)).ok();
+        Some(SftpDestination {
+            host: var("HOST")?,
+            port: var("PORT").and_then(|port| port.parse().ok()),
+            username: var("USER")?,
+            privatekey: var("KEY")?,
+            publickey: None,
+            passphrase: None,
+            knownhosts: var("KNOWNHOSTS"),
+            skiphostkeycheck: false,
+            remotepath: var("DIR")?,
+        })
+    }
+
+    #[test]
+    #[ignore = "needs an SFTP server, see test_server"]
+    fn upload_replaces_an_existing_remote_file_and_retention_removes_it() {
+        let destination = test_server().expect("LOGRC_TEST_SFTP_* variables are set");
+        let (session, sftp) = connect(&destination).unwrap();
+
+        let local_dir = env::temp_dir().join(format!("logrc-sftp-test-{}", std::process::id()));
+        fs::create_dir_all(&local_dir).unwrap();
+        let local_path = local_dir.join("sftptest.log");
+
+        // Uploading the same name twice has to replace the first copy
+        fs::write(&local_path, "first").unwrap();
+        let remote_path = upload_file(&session, &sftp, &local_path, &destination.remotepath).unwrap();
+        fs::write(&local_path, "second upload").unwrap();
+        assert_eq!(upload_file(&session, &sftp, &local_path, &destination.remotepath).unwrap(), remote_path);
+        assert_eq!(sftp.stat(&remote_path).unwrap().size, Some(13));
+        assert!(sftp.stat(&remote_join(&destination.remotepath, "sftptest.log.part")).is_err());
+        assert!(sftp.stat(&remote_join(&destination.remotepath, "sftptest.log.old")).is_err());
+
+        // Age the remote file past retention
+        let mut stat = sftp.stat(&remote_path).unwrap();
+        stat.mtime = Some(0);
+        stat.atime = Some(0);
+        sftp.setstat(&remote_path, stat).unwrap();
+
+        let outcome = remove_old_sftp_files(&destination, "sftptest", &1, &RetryPolicy::default(), &LogObserver, false).unwrap();
+        assert_eq!(outcome.deleted.len(), 1);
+        assert!(outcome.failed.is_empty());
+        assert!(sftp.stat(&remote_path).is_err());
+
+        fs::remove_dir_all(&local_dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use log::{error, info, warn};
+use log_rc::{Application, Directory, PendingArchive};
+use serde::{Deserialize, Serialize};
+use std::{fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}};
+
+// Where a run that ran out of budget stopped, so the next run carries on from there
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct RunState {
+    // The rule the next run starts with, see Directory::key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nextrule: Option<String>,
+    // Archives left part way through a date group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pendingarchives: Vec<PendingArchive>,
+}
+
+// Split the saved rule into the rule to resume from, when it is one of the rules about to run, and the rule
+// to keep as the place to resume from when it is not. A run of only some rules must not lose where another stopped
+pub fn resume_point<'a>(nextrule: Option<String>, mut rules: impl Iterator<Item = &'a Directory>) -> (Option<String>, Option<String>) {
+    match nextrule {
+        Some(nextrule) if rules.any(|dir| dir.key() == nextrule) => (Some(nextrule), None),
+        nextrule => (None, nextrule),
+    }
+}
+
+// The default state file sits next to the config file
+pub fn state_path(config_path: &Path, application: &Application) -> PathBuf {
+    application.statefile.as_ref().map(PathBuf::from)
+        .unwrap_or_else(|| config_path.parent().unwrap_or(Path::new("")).join("LogRC.state.json"))
+}
+
+// A missing or unreadable state file means starting from the first rule
+pub fn load(path: &Path) -> RunState {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return RunState::default(),
+        Err(e) => {
+            warn!("Could not read the state file '{}', starting from the first rule: {}", path.display(), e);
+            return RunState::default();
+        }
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Could not parse the state file '{}', starting from the first rule: {}", path.display(), e);
+            RunState::default()
+        }
+    }
+}
+
+// Remove the state file once a run finishes everything, otherwise write it through a temp file
+pub fn save(path: &Path, state: &RunState) {
+    let result = if *state == RunState::default() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        write_state(path, state)
+    };
+
+    match result {
+        Ok(()) if state.nextrule.is_some() => info!("Saved the run state to '{}', the next run starts with '{}'", path.display(), state.nextrule.as_deref().unwrap_or_default()),
+        Ok(()) => (),
+        Err(e) => error!("There was an issue saving the state file '{}': {}", path.display(), e),
+    }
+}
+
+fn write_state(path: &Path, state: &RunState) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = PathBuf::from(format!("{}.{}.tmp", path.display(), std::process::id()));
+    {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp_path, path)
+}
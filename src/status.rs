@@ -0,0 +1,90 @@
+use log::info;
+use std::{fs::{File, remove_file}, io::{self, Write, ErrorKind}, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::{LogRcError, WithPath}, outcome::Outcome};
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusFormat {
+    #[default]
+    Json,
+    Toml,
+}
+
+#[derive(Serialize)]
+pub struct StatusRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub source: String,
+    pub destination: String,
+    pub filenamecontains: String,
+    pub started: String,
+    pub finished: String,
+    pub files_scanned: usize,
+    pub files_deleted: usize,
+    pub files_compressed: usize,
+    pub files_moved: usize,
+    pub bytes_deleted: u64,
+    pub bytes_compressed: u64,
+    pub bytes_moved: u64,
+    pub error_count: usize,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl StatusRecord {
+    pub fn new(name: Option<&str>, source: &str, destination: &str, filenamecontains: &str, started: String, finished: String, outcome: Outcome) -> StatusRecord {
+        StatusRecord {
+            name: name.map(str::to_string),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            filenamecontains: filenamecontains.to_string(),
+            started,
+            finished,
+            files_scanned: outcome.scanned.len(),
+            files_deleted: outcome.deleted.len(),
+            files_compressed: outcome.files_compressed(),
+            files_moved: outcome.moved.len(),
+            bytes_deleted: outcome.deleted.iter().map(|file| file.bytes).sum(),
+            bytes_compressed: outcome.bytes_before_compression(),
+            bytes_moved: outcome.moved.iter().map(|file| file.bytes).sum(),
+            error_count: outcome.errors.len(),
+            outcome,
+        }
+    }
+}
+
+// Default name used when the [directory]statusfile setting is not set
+pub fn default_status_file_name(filename_contains: &str) -> String {
+    format!("{} files have been moved.status", filename_contains)
+}
+
+pub fn create_status_file(dir_path: &str, file_name: &str, format: StatusFormat, record: &StatusRecord, dry_run: bool) -> Result<PathBuf, LogRcError> {
+
+    let file_path: PathBuf = Path::new(dir_path).join(file_name);
+    let content = match format {
+        StatusFormat::Json => serde_json::to_string_pretty(record).map_err(io::Error::other).with_path(&file_path)?,
+        StatusFormat::Toml => toml::to_string_pretty(record).map_err(io::Error::other).with_path(&file_path)?,
+    };
+
+    if dry_run {
+        info!("Would create a status file at '{}'", file_path.display());
+        return Ok(file_path);
+    }
+
+    // Attempt to remove the file if it exists
+    match remove_file(&file_path) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::NotFound => (), // File doesn't exist, which is fine
+        Err(e) => return Err(LogRcError::io(&file_path, e)), // Other errors should be propagated
+    }
+
+    // Make the status file with content
+    let mut file = File::create(&file_path).with_path(&file_path)?;
+    file.write_all(content.as_bytes()).with_path(&file_path)?;
+    info!("Created a status file at '{}'", file_path.display());
+
+    Ok(file_path)
+
+}
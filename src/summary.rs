@@ -0,0 +1,157 @@
+use log::info;
+use std::{fs::File, io::{self, Write}, path::Path};
+use serde::Serialize;
+
+use crate::{error::{LogRcError, WithPath}, outcome::Outcome};
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Summary {
+    pub files_scanned: usize,
+    pub files_compressed: usize,
+    pub files_moved: usize,
+    pub files_deleted: usize,
+    pub bytes_moved: u64,
+    pub bytes_deleted: u64,
+    pub bytes_before_compression: u64,
+    pub bytes_after_compression: u64,
+    pub compression_ratio: f64,
+    pub errors: usize,
+    // Files left in place because an operation on them kept failing
+    pub files_failed: usize,
+}
+
+impl Summary {
+    pub fn from_outcome(outcome: &Outcome) -> Summary {
+        let mut summary = Summary {
+            files_scanned: outcome.scanned.len(),
+            files_compressed: outcome.files_compressed(),
+            files_moved: outcome.moved.len(),
+            files_deleted: outcome.deleted.len(),
+            bytes_moved: outcome.moved.iter().map(|file| file.bytes).sum(),
+            bytes_deleted: outcome.deleted.iter().map(|file| file.bytes).sum(),
+            bytes_before_compression: outcome.bytes_before_compression(),
+            bytes_after_compression: outcome.bytes_after_compression(),
+            compression_ratio: 0.0,
+            errors: outcome.errors.len(),
+            files_failed: outcome.failed.len(),
+        };
+        summary.update_ratio();
+        summary
+    }
+
+    pub fn add(&mut self, other: &Summary) {
+        self.files_scanned += other.files_scanned;
+        self.files_compressed += other.files_compressed;
+        self.files_moved += other.files_moved;
+        self.files_deleted += other.files_deleted;
+        self.bytes_moved += other.bytes_moved;
+        self.bytes_deleted += other.bytes_deleted;
+        self.bytes_before_compression += other.bytes_before_compression;
+        self.bytes_after_compression += other.bytes_after_compression;
+        self.errors += other.errors;
+        self.files_failed += other.files_failed;
+        self.update_ratio();
+    }
+
+    pub fn files_processed(&self) -> usize {
+        self.files_compressed + self.files_moved + self.files_deleted
+    }
+
+    pub fn bytes_processed(&self) -> u64 {
+        self.bytes_before_compression + self.bytes_moved + self.bytes_deleted
+    }
+
+    // Space given back by deleting files and by compressing them
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_deleted + self.bytes_before_compression.saturating_sub(self.bytes_after_compression)
+    }
+
+    // Original size divided by compressed size, 0 when nothing was compressed
+    fn update_ratio(&mut self) {
+        self.compression_ratio = if self.bytes_after_compression > 0 {
+            self.bytes_before_compression as f64 / self.bytes_after_compression as f64
+        } else {
+            0.0
+        };
+    }
+
+    pub fn log(&self, label: &str) {
+        info!("Summary for {}: scanned {}, compressed {}, moved {}, deleted {}, bytes before compression {}, bytes after compression {}, compression ratio {:.2}:1, errors {}, failed files {}",
+            label,
+            self.files_scanned,
+            self.files_compressed,
+            self.files_moved,
+            self.files_deleted,
+            self.bytes_before_compression,
+            self.bytes_after_compression,
+            self.compression_ratio,
+            self.errors,
+            self.files_failed,
+        );
+    }
+}
+
+// How log lines refer to a directory rule
+pub fn rule_label(name: Option<&str>, path: &str, filenamecontains: &str) -> String {
+    match name {
+        Some(name) => format!("Rule '{}' (Path '{}', Name '{}')", name, path, filenamecontains),
+        None => format!("Path '{}', Name '{}'", path, filenamecontains),
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DirectorySummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub path: String,
+    pub filenamecontains: String,
+    pub finished: String,
+    pub finished_timestamp: i64,
+    pub archive_count: u64,
+    pub archive_bytes: u64,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RunSummary {
+    pub started: String,
+    pub finished: String,
+    pub elapsed_seconds: u64,
+    pub directories: Vec<DirectorySummary>,
+    pub total: Summary,
+}
+
+impl RunSummary {
+    pub fn new(started: String, finished: String, elapsed_seconds: u64, directories: Vec<DirectorySummary>) -> RunSummary {
+        let mut total = Summary::default();
+        for directory in &directories {
+            total.add(&directory.summary);
+        }
+
+        RunSummary { started, finished, elapsed_seconds, directories, total }
+    }
+
+    pub fn log(&self) {
+        for directory in &self.directories {
+            directory.summary.log(&rule_label(directory.name.as_deref(), &directory.path, &directory.filenamecontains));
+        }
+        self.total.log("all directories");
+    }
+}
+
+pub fn write_summary_file(file_path: &str, summary: &RunSummary) -> Result<(), LogRcError> {
+
+    let content = serde_json::to_string_pretty(summary).map_err(io::Error::other).with_path(file_path)?;
+
+    // Make sure the parent directory is there before writing
+    if let Some(parent) = Path::new(file_path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_path(parent)?;
+    }
+
+    let mut file = File::create(file_path).with_path(file_path)?;
+    file.write_all(content.as_bytes()).with_path(file_path)?;
+    info!("Created a summary file at '{}'", file_path);
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{fs::{self, File}, io::{self, Read, Seek, SeekFrom, Write}, path::Path, thread, time::{Duration, Instant}};
+use filetime::FileTime;
+use serde::Deserialize;
+
+use crate::error::{LogRcError, WithPath};
+
+// Bytes per second limits for reading and writing files, None means no limit
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct IoLimits {
+    pub maxreadrate: Option<u64>,
+    pub maxwriterate: Option<u64>,
+}
+
+// Keeps a running total and sleeps whenever it gets ahead of the allowed rate
+struct RateLimit {
+    rate: Option<u64>,
+    started: Instant,
+    bytes: u64,
+}
+
+impl RateLimit {
+    fn new(rate: Option<u64>) -> RateLimit {
+        RateLimit { rate, started: Instant::now(), bytes: 0 }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        let rate = match self.rate {
+            Some(rate) if rate > 0 => rate,
+            _ => return,
+        };
+
+        self.bytes += bytes as u64;
+        let allowed = Duration::from_secs_f64(self.bytes as f64 / rate as f64);
+        let elapsed = self.started.elapsed();
+        if allowed > elapsed {
+            thread::sleep(allowed - elapsed);
+        }
+    }
+}
+
+pub struct ThrottledReader<R> {
+    inner: R,
+    limit: RateLimit,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, rate: Option<u64>) -> ThrottledReader<R> {
+        ThrottledReader { inner, limit: RateLimit::new(rate) }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.limit.record(read);
+        Ok(read)
+    }
+}
+
+pub struct ThrottledWriter<W> {
+    inner: W,
+    limit: RateLimit,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    pub fn new(inner: W, rate: Option<u64>) -> ThrottledWriter<W> {
+        ThrottledWriter { inner, limit: RateLimit::new(rate) }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.limit.record(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Appending to a zip file reads back its central directory, which does not count towards the rate
+impl<W: Read> Read for ThrottledWriter<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+// The zip writer goes back to fill in headers, which does not count towards the rate
+impl<W: Seek> Seek for ThrottledWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// Copy a file to another filesystem within the limits, then remove the original.
+// The copy goes to a temp name first so a partial copy is never mistaken for the real file
+pub fn copy_and_remove(from: &Path, to: &Path, limits: &IoLimits) -> Result<(), LogRcError> {
+    let temp_path = to.with_file_name(format!("{}.part", to.file_name().unwrap_or_default().to_string_lossy()));
+    let metadata = fs::metadata(from).with_path(from)?;
+
+    let copy = || -> Result<(), LogRcError> {
+        let mut reader = ThrottledReader::new(File::open(from).with_path(from)?, limits.maxreadrate);
+        let mut writer = ThrottledWriter::new(File::create(&temp_path).with_path(&temp_path)?, limits.maxwriterate);
+        io::copy(&mut reader, &mut writer).with_path(&temp_path)?;
+        writer.inner.sync_all().with_path(&temp_path)?;
+        Ok(())
+    };
+    if let Err(e) = copy() {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    // Keep the modified time so retention in the destination still sees the file's real age
+    filetime::set_file_mtime(&temp_path, FileTime::from_last_modification_time(&metadata)).with_path(&temp_path)?;
+    fs::rename(&temp_path, to).with_path(to)?;
+    fs::remove_file(from).with_path(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reader_keeps_to_the_rate() {
+        let data = vec![7u8; 20_000];
+        let started = Instant::now();
+        let mut copied = Vec::new();
+        io::copy(&mut ThrottledReader::new(data.as_slice(), Some(100_000)), &mut copied).unwrap();
+
+        // 20 KB at 100 KB/s takes about 200 ms
+        assert!(started.elapsed() >= Duration::from_millis(190));
+        assert_eq!(copied, data);
+    }
+
+    #[test]
+    fn writer_keeps_to_the_rate() {
+        let started = Instant::now();
+        let mut writer = ThrottledWriter::new(Vec::new(), Some(100_000));
+        writer.write_all(&[1u8; 10_000]).unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(90));
+        assert_eq!(writer.inner.len(), 10_000);
+    }
+
+    #[test]
+    fn no_limit_does_not_wait() {
+        let started = Instant::now();
+        let mut copied = Vec::new();
+        io::copy(&mut ThrottledReader::new([0u8; 1_000_000].as_slice(), None), &mut ThrottledWriter::new(&mut copied, Some(0))).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(copied.len(), 1_000_000);
+    }
+
+    #[test]
+    fn copy_and_remove_keeps_the_contents_and_modified_time() {
+        let dir = std::env::temp_dir().join(format!("logrc-throttle-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("app.log");
+        let to = dir.join("moved.log");
+        fs::write(&from, "line\n").unwrap();
+        let modified = FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(&from, modified).unwrap();
+
+        copy_and_remove(&from, &to, &IoLimits::default()).unwrap();
+
+        assert!(!from.exists());
+        assert!(!dir.join("moved.log.part").exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "line\n");
+        assert_eq!(FileTime::from_last_modification_time(&fs::metadata(&to).unwrap()), modified);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_copy_leaves_the_original_and_no_temp_file() {
+        let dir = std::env::temp_dir().join(format!("logrc-throttle-fail-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("app.log");
+        fs::write(&from, "line\n").unwrap();
+
+        assert!(copy_and_remove(&from, &dir.join("missing/moved.log"), &IoLimits::default()).is_err());
+        assert!(from.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use log::{info, error};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::mpsc::{channel, Receiver}, time::{Duration, Instant}};
+
+use log_rc::Directory;
+
+pub fn quiet_period(dir: &Directory) -> u64 {
+    dir.active.quiet_period()
+}
+
+// Watches the path of each rule and keeps track of matching files until they stop changing
+pub struct FileWatch {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    rules: Vec<Directory>,
+    // The rule each changed file belongs to and when it last changed
+    pending: HashMap<PathBuf, (usize, Instant)>,
+}
+
+impl FileWatch {
+    pub fn start(rules: Vec<Directory>) -> notify::Result<FileWatch> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        // Compression walks sub directories too, so watch them as well
+        for dir in &rules {
+            watcher.watch(Path::new(&dir.path), RecursiveMode::Recursive)?;
+            info!("Watching {} for files to compress after {} quiet second(s)", dir.label(), quiet_period(dir));
+        }
+
+        Ok(FileWatch { _watcher: watcher, events, rules, pending: HashMap::new() })
+    }
+
+    // Take in the latest events and hand back the files that have been quiet long enough, by rule
+    pub fn quiet_files(&mut self) -> Vec<(Directory, Vec<PathBuf>)> {
+        let now = Instant::now();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) => self.record(event, now),
+                Err(e) => error!("There was an issue watching for file changes: {}", e),
+            }
+        }
+
+        let rules = &self.rules;
+        let mut quiet: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+        self.pending.retain(|path, (position, changed)| {
+            if now.duration_since(*changed) < Duration::from_secs(quiet_period(&rules[*position])) {
+                return true;
+            }
+            // Files that were rotated away or removed in the meantime are dropped
+            if path.is_file() {
+                quiet.entry(*position).or_default().push(path.clone());
+            }
+            false
+        });
+
+        quiet.into_iter().map(|(position, files)| (self.rules[position].clone(), files)).collect()
+    }
+
+    fn record(&mut self, event: Event, now: Instant) {
+        for path in event.paths {
+            if let EventKind::Remove(_) = event.kind {
+                self.pending.remove(&path);
+                continue;
+            }
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) || !path.is_file() {
+                continue;
+            }
+
+            // Only log and txt files are compressed
+            let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if !matches!(path.extension().and_then(|ext| ext.to_str()), Some("log") | Some("txt")) {
+                continue;
+            }
+
+            let rule = self.rules.iter().position(|dir| path.starts_with(&dir.path) && file_name.contains(&dir.filenamecontains));
+            if let Some(position) = rule {
+                self.pending.insert(path, (position, now));
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use log::{Log, Metadata, Record};
+use std::{cell::RefCell, collections::VecDeque, fs, sync::Mutex, thread};
+
+use crate::{Directory, RunBudget};
+use crate::engine::{Engine, RuleReport};
+
+thread_local! {
+    static RULE_LABEL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Wraps the real logger so the lines a worker thread logs for a rule start with the rule's label.
+// Lines are written straight away, the label tells apart the rules running side by side
+pub struct RuleLogger {
+    inner: Box<dyn Log>,
+}
+
+impl RuleLogger {
+    pub fn new(inner: Box<dyn Log>) -> RuleLogger {
+        RuleLogger { inner }
+    }
+}
+
+impl Log for RuleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        RULE_LABEL.with(|label| match label.borrow().as_deref() {
+            Some(label) => self.inner.log(&Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("[{}] {}", label, record.args()))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build()),
+            None => self.inner.log(record),
+        });
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Run a closure with its log lines labelled with the rule it is working on
+fn with_rule_log<T>(dir: &Directory, f: impl FnOnce() -> T) -> T {
+    RULE_LABEL.with(|label| *label.borrow_mut() = Some(dir.label()));
+    let result = f();
+    RULE_LABEL.with(|label| *label.borrow_mut() = None);
+    result
+}
+
+// Rules whose paths are on the same filesystem share a key, so their I/O is not run side by side
+#[cfg(unix)]
+fn device_key(path: &str) -> String {
+    use std::os::unix::fs::MetadataExt;
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.dev().to_string(),
+        Err(_) => path.to_string(),
+    }
+}
+
+#[cfg(not(unix))]
+fn device_key(path: &str) -> String {
+    use std::path::{Component, Path};
+    match Path::new(path).components().next() {
+        Some(Component::Prefix(prefix)) => prefix.as_os_str().to_string_lossy().to_uppercase(),
+        _ => fs::canonicalize(path).ok()
+            .and_then(|path| path.components().next().map(|component| component.as_os_str().to_string_lossy().to_uppercase()))
+            .unwrap_or_else(|| path.to_string()),
+    }
+}
+
+// Rules that run one after another and the filesystems they use
+type RuleGroup<'a> = (Vec<String>, Vec<(usize, &'a Directory)>);
+
+// Every filesystem a rule reads or writes, the rule's path and the folder it moves files to
+fn device_keys(dir: &Directory) -> Vec<String> {
+    let mut keys = vec![device_key(&dir.path)];
+    if !dir.movetopath.is_empty() {
+        let key = device_key(&dir.movetopath);
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+// Process the rules with up to workers threads. Rules on the same filesystem, for their path or movetopath,
+// run one after another in config order.
+// Results come back in the order the rules were given. Rules not started before the budget ran out are left out
+pub(crate) fn process_rules(engine: &Engine, rules: &[(usize, &Directory)], workers: usize, budget: &RunBudget, dry_run: bool) -> Vec<RuleReport> {
+
+    if workers <= 1 || rules.len() <= 1 {
+        return rules.iter()
+            .filter(|(_, dir)| has_budget(engine, dir, budget))
+            .map(|(index, dir)| engine.run_rule(*index, dir, budget, dry_run))
+            .collect();
+    }
+
+    // Rules that share any filesystem go in one group, which keeps the given order so a resumed run
+    // starts with the rule it stopped on
+    let mut groups: Vec<RuleGroup> = Vec::new();
+    for (index, dir) in rules {
+        let mut keys = device_keys(dir);
+        let mut group = vec![(*index, *dir)];
+        let mut position = groups.len();
+        while let Some(shared) = groups.iter().position(|(other, _)| other.iter().any(|key| keys.contains(key))) {
+            let (other_keys, other_rules) = groups.remove(shared);
+            keys.extend(other_keys.into_iter().filter(|key| !keys.contains(key)).collect::<Vec<_>>());
+            group.extend(other_rules);
+            position = position.min(shared);
+        }
+        group.sort_by_key(|(rule, _)| rules.iter().position(|(other, _)| other == rule));
+        groups.insert(position.min(groups.len()), (keys, group));
+    }
+
+    let queue = Mutex::new(groups.into_iter().map(|(_, group)| group).collect::<VecDeque<_>>());
+    let results = Mutex::new(Vec::new());
+    let threads = workers.min(queue.lock().unwrap().len());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).pop_front();
+                let Some(group) = next else { break };
+
+                for (index, dir) in group {
+                    if !has_budget(engine, dir, budget) {
+                        continue;
+                    }
+                    let report = with_rule_log(dir, || engine.run_rule(index, dir, budget, dry_run));
+                    results.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(report);
+                }
+            });
+        }
+    });
+
+    // Put the results back in the order the rules were given
+    let mut results = results.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+    results.sort_by_key(|report| rules.iter().position(|(rule, _)| *rule == report.rule));
+    results
+}
+
+fn has_budget(engine: &Engine, dir: &Directory, budget: &RunBudget) -> bool {
+    if budget.exhausted() {
+        engine.observer().on_budget_exhausted(&format!("Run budget is used up, {} will run next time", dir.label()));
+        return false;
+    }
+    true
+}